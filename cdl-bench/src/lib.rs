@@ -7,9 +7,8 @@ extern crate cdl_core;
 #[cfg(test)]
 mod tests {
     use test::{Bencher, black_box};
-    use cdl_core::{select_field, select_entity, compile, Lexer};
-
-    use _SCRIPT;
+    use cdl_core::{select_field, select_entity, compile, Selector, ParseResult};
+    use cdl_core::build::{entity, page, str, widget, Doc};
 
 //    #[bench]
 //    fn bench_lex(b: &mut Bencher) {
@@ -19,9 +18,7 @@ mod tests {
 //        });
 //    }
 
-    #[bench]
-    fn bench_select(b: &mut Bencher) {
-        let cdl = "
+    const SELECT_SCRIPT: &str = "
    datatable kpi data1 {
       type : nps
       vpath : t1:q1
@@ -45,14 +42,132 @@ mod tests {
         label : \"KPI\"
       }
     }
-".to_string();
-        let root = compile(cdl).unwrap();
+";
+
+    // parses the selectors on every query
+    #[bench]
+    fn bench_select(b: &mut Bencher) {
+        let root = compile(SELECT_SCRIPT.to_string()).unwrap();
 
         b.iter(|| {
             black_box(select_entity(&root, "page > widget[kpi]"));
             black_box(select_field(&root, ".label"));
         });
     }
+
+    // parses the selectors once
+    #[bench]
+    fn bench_select_compiled(b: &mut Bencher) {
+        let root = compile(SELECT_SCRIPT.to_string()).unwrap();
+        let widgets = Selector::compile("page > widget[kpi]").unwrap();
+        let labels = Selector::compile(".label").unwrap();
+
+        b.iter(|| {
+            black_box(widgets.entities(&root));
+            black_box(labels.fields(&root));
+        });
+    }
+
+    // pages of 100 widgets with 8 columns each, 901 entities a page
+    fn wide_document(pages: usize) -> ParseResult {
+        let mut doc = Doc::new();
+        for p in 0..pages {
+            let mut page = page().id(&format!("p{}", p));
+            for w in 0..100 {
+                let mut widget = widget(if w % 10 == 0 { "kpi" } else { "table" }).field("label", str("Widget"));
+                for c in 0..8 {
+                    widget = widget.child(entity("column").sub_type("value").id(&format!("c{}", c))
+                        .field("label", str("Column"))
+                        .field("value", str("Value")));
+                }
+                page = page.child(widget);
+            }
+            doc = doc.add_entity(page);
+        }
+        doc.build()
+    }
+
+    // 200 chains of 500 nested groups with a tile at the bottom, 100200 entities
+    fn deep_document() -> ParseResult {
+        let mut doc = Doc::new();
+        for _ in 0..200 {
+            let mut chain = entity("tile").field("label", str("Tile"));
+            for _ in 0..500 {
+                chain = entity("group").child(chain);
+            }
+            doc = doc.add_entity(chain);
+        }
+        doc.build()
+    }
+
+    fn index(b: &mut Bencher, pages: usize) {
+        let mut root = wide_document(pages);
+
+        b.iter(|| {
+            root.invalidate_index();
+            black_box(root.index().document_order().len());
+        });
+    }
+
+    fn select(b: &mut Bencher, pages: usize) {
+        let root = wide_document(pages);
+        let widgets = Selector::compile("page > widget[kpi]").unwrap();
+        let labels = Selector::compile(".label").unwrap();
+        let column = Selector::compile(&format!("page#p{} widget #c3", pages / 2)).unwrap();
+
+        b.iter(|| {
+            black_box(widgets.entities(&root));
+            black_box(labels.fields(&root));
+            black_box(column.entities(&root));
+        });
+    }
+
+    // 901 entities
+    #[bench]
+    fn bench_index_small(b: &mut Bencher) {
+        index(b, 1);
+    }
+
+    // 9010 entities
+    #[bench]
+    fn bench_index_medium(b: &mut Bencher) {
+        index(b, 10);
+    }
+
+    // 90100 entities
+    #[bench]
+    fn bench_index_large(b: &mut Bencher) {
+        index(b, 100);
+    }
+
+    #[bench]
+    fn bench_select_small(b: &mut Bencher) {
+        select(b, 1);
+    }
+
+    #[bench]
+    fn bench_select_medium(b: &mut Bencher) {
+        select(b, 10);
+    }
+
+    #[bench]
+    fn bench_select_large(b: &mut Bencher) {
+        select(b, 100);
+    }
+
+    #[bench]
+    fn bench_select_deep(b: &mut Bencher) {
+        let root = deep_document();
+        let tiles = Selector::compile("group group tile").unwrap();
+        let groups = Selector::compile("group:has(> tile)").unwrap();
+        let labels = Selector::compile("group .label").unwrap();
+
+        b.iter(|| {
+            black_box(tiles.entities(&root));
+            black_box(groups.entities(&root));
+            black_box(labels.fields(&root));
+        });
+    }
 }
 
 
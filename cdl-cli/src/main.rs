@@ -5,11 +5,32 @@ use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
-use cdl_core::compile;
+use cdl_core::{comments, compile, dashboard_html, dashboard_markdown, format, highlight_ansi, highlight_html, print_hierarchy,
+               print_node, EntityRef, HierarchyOptions, ParseResult, PrintOptions, Selector};
 use std::time::{ Instant};
 
+// config file picked up from the working directory by `fmt` when no --config is given
+const FMT_CONFIG: &str = ".cdlfmt";
+
+type Command = fn(&[String]) -> Result<(), String>;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let command: Option<Command> = match args.get(1).map(|a| a.as_str()) {
+        Some("fmt") => Some(fmt),
+        Some("docs") => Some(docs),
+        Some("tree") => Some(tree),
+        Some("highlight") => Some(highlight),
+        Some("select") => Some(select),
+        _ => None,
+    };
+    if let Some(command) = command {
+        if let Err(e) = command(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     let filename;
     match args.len() {
         1 => {
@@ -55,3 +76,164 @@ fn main() {
     println!("Compiled {:?}", compiled);
 
 }
+
+// cdl fmt [--config <file>] [--canonical | --minify] [--write] <file>...
+// Prints the formatted scripts, or with --write formats them in place. --canonical prints the normalised
+// form used to compare scripts and --minify the smallest one, both ignore the config. Formatting leaves
+// comments out, so --write refuses scripts with comments and writes nothing.
+fn fmt(args: &[String]) -> Result<(), String> {
+    let mut config = None;
+    let mut write = false;
+    let mut canonical = false;
+    let mut minify = false;
+    let mut files = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--config" => match it.next() {
+                Some(path) => config = Some(path.to_string()),
+                None => return Err("--config needs a file".to_string()),
+            },
+            "--write" => write = true,
+            "--canonical" => canonical = true,
+            "--minify" => minify = true,
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        return Err("Usage: fmt [--config <file>] [--canonical | --minify] [--write] <file>...".to_string());
+    }
+
+    let options = match config {
+        _ if canonical => PrintOptions::canonical(),
+        _ if minify => PrintOptions::minified(),
+        Some(path) => PrintOptions::from_file(path)?,
+        None if Path::new(FMT_CONFIG).exists() => PrintOptions::from_file(FMT_CONFIG)?,
+        None => PrintOptions::default(),
+    };
+
+    let mut formatted_files = Vec::new();
+    for filename in files {
+        let cdl_script = read_script(filename)?;
+        if write {
+            let comments = comments(&cdl_script).map_err(|e| format!("{}: {}", filename, e))?;
+            if !comments.is_empty() {
+                return Err(format!("{}: has {} comment(s) that formatting would remove, nothing was written",
+                                   filename, comments.len()));
+            }
+        }
+        let formatted = format(cdl_script, &options).map_err(|e| format!("{}: {}", filename, e))?;
+        formatted_files.push((filename, formatted));
+    }
+    for (filename, formatted) in formatted_files {
+        if write {
+            File::create(filename)
+                .and_then(|mut file| file.write_all(formatted.as_bytes()))
+                .map_err(|e| format!("Couldn't write {}: {}", filename, e))?;
+        } else {
+            print!("{}", formatted);
+        }
+    }
+    Ok(())
+}
+
+// cdl highlight [--html] <file>
+// Prints the script with ANSI colours, or as HTML for documentation.
+fn highlight(args: &[String]) -> Result<(), String> {
+    let (html, filename) = match args {
+        [flag, filename] if flag == "--html" => (true, filename),
+        [filename] => (false, filename),
+        _ => return Err("Usage: highlight [--html] <file>".to_string()),
+    };
+    let cdl_script = read_script(filename)?;
+    let highlighted = if html { highlight_html(&cdl_script)? } else { highlight_ansi(&cdl_script)? };
+    print!("{}", highlighted);
+    Ok(())
+}
+
+// cdl docs [--html] <file>
+// Prints a description of the dashboard as Markdown, or as HTML.
+fn docs(args: &[String]) -> Result<(), String> {
+    let (html, filename) = match args {
+        [flag, filename] if flag == "--html" => (true, filename),
+        [filename] => (false, filename),
+        _ => return Err("Usage: docs [--html] <file>".to_string()),
+    };
+    let cdl_script = read_script(filename)?;
+    let pr = compile(cdl_script).map_err(|e| format!("{}: {}", filename, e))?;
+    print!("{}", if html { dashboard_html(&pr) } else { dashboard_markdown(&pr) });
+    Ok(())
+}
+
+// cdl tree [--count] [--field <name>]... <file>
+// Draws the entity hierarchy, with the number of fields and the values of the named fields.
+fn tree(args: &[String]) -> Result<(), String> {
+    let mut options = HierarchyOptions::default();
+    let mut filename = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--count" => options.field_count = true,
+            "--field" => match it.next() {
+                Some(field) => options.fields.push(field.to_string()),
+                None => return Err("--field needs a field name".to_string()),
+            },
+            _ if filename.is_none() => filename = Some(arg),
+            _ => return Err("Usage: tree [--count] [--field <name>]... <file>".to_string()),
+        }
+    }
+    let filename = filename.ok_or_else(|| "Usage: tree [--count] [--field <name>]... <file>".to_string())?;
+    let cdl_script = read_script(filename)?;
+    let pr = compile(cdl_script).map_err(|e| format!("{}: {}", filename, e))?;
+    print!("{}", print_hierarchy(&pr, &options));
+    Ok(())
+}
+
+// cdl select [--fields] <selector> <file>
+// Prints the entities, or with --fields the fields, matching the selector, each after a comment with the
+// entities it is in.
+fn select(args: &[String]) -> Result<(), String> {
+    let (fields, selector, filename) = match args {
+        [flag, selector, filename] if flag == "--fields" => (true, selector, filename),
+        [selector, filename] => (false, selector, filename),
+        _ => return Err("Usage: select [--fields] <selector> <file>".to_string()),
+    };
+    let selector = if fields { Selector::compile_fields(selector) } else { Selector::compile(selector) };
+    let selector = selector.map_err(|e| format!("Invalid selector: {}", e))?;
+    let cdl_script = read_script(filename)?;
+    let pr = compile(cdl_script).map_err(|e| format!("{}: {}", filename, e))?;
+    let options = PrintOptions::default();
+    let matches = if fields {
+        selector.field_ids(&pr).into_iter()
+            .map(|m| (m.node(), m.path.iter().chain(Some(&m.entity)).cloned().collect()))
+            .collect()
+    } else {
+        selector.entity_ids(&pr).into_iter().map(|m| (m.node(), m.path)).collect::<Vec<_>>()
+    };
+    for (node, path) in matches {
+        println!("// {}", print_path(&pr, &path));
+        print!("{}", print_node(&pr, node, &options));
+    }
+    Ok(())
+}
+
+fn print_path(pr: &ParseResult, path: &[EntityRef]) -> String {
+    let headers: Vec<String> = path.iter()
+        .map(|e| pr.get_entity(*e))
+        .map(|e| {
+            let mut header = vec![e.main_type.clone()];
+            header.extend(e.sub_type.clone());
+            header.extend(e.identifier.as_ref().map(|id| format!("#{}", id)));
+            header.join(" ")
+        })
+        .collect();
+    if headers.is_empty() { "(top level)".to_string() } else { headers.join(" > ") }
+}
+
+fn read_script(filename: &str) -> Result<String, String> {
+    let mut cdl_script = String::new();
+    File::open(filename)
+        .and_then(|mut file| file.read_to_string(&mut cdl_script))
+        .map_err(|e| format!("Couldn't read {}: {}", filename, e))?;
+    Ok(cdl_script)
+}
@@ -0,0 +1,315 @@
+use parse::{AstEntityNode, AstFieldNode, AstFunctionNode, AstIdentifierNode, AstNumberNode, AstOperatorNode,
+            AstReferenceNode, AstStringNode, AstUnaryOperatorNode, AstVPathNode, EntityExprRef, EntityRef, Expr,
+            ParseResult};
+
+/// Builds a whole document. Calls after `entity` describe the last root entity added.
+///
+/// ```
+/// use cdl_core::build::{Doc, widget, str};
+///
+/// let pr = Doc::new()
+///     .entity("page").id("overview")
+///     .child(widget("kpi").field("label", str("KPI")))
+///     .build();
+/// assert_eq!(cdl_core::print(pr), "page #overview {\n    widget kpi {\n        label: \"KPI\"\n    }\n}\n");
+/// ```
+#[derive(Debug, Default)]
+pub struct Doc {
+    entities: Vec<EntityBuilder>,
+}
+
+impl Doc {
+    pub fn new() -> Doc {
+        Doc {
+            entities: Vec::new(),
+        }
+    }
+
+    pub fn entity(mut self, main_type: &str) -> Doc {
+        self.entities.push(entity(main_type));
+        self
+    }
+
+    pub fn add_entity(mut self, entity: EntityBuilder) -> Doc {
+        self.entities.push(entity);
+        self
+    }
+
+    pub fn sub_type(self, sub_type: &str) -> Doc {
+        self.with_current(|e| e.sub_type(sub_type))
+    }
+
+    pub fn id(self, identifier: &str) -> Doc {
+        self.with_current(|e| e.id(identifier))
+    }
+
+    pub fn reference(self, reference: &str) -> Doc {
+        self.with_current(|e| e.reference(reference))
+    }
+
+    pub fn field(self, identifier: &str, value: ExprBuilder) -> Doc {
+        self.with_current(|e| e.field(identifier, value))
+    }
+
+    pub fn child(self, child: EntityBuilder) -> Doc {
+        self.with_current(|e| e.child(child))
+    }
+
+    pub fn build(self) -> ParseResult {
+        let mut pr = ParseResult::new();
+        for entity in self.entities {
+            let index = entity.add_to(&mut pr);
+            pr.root.children.push(index);
+        }
+        pr
+    }
+
+    fn with_current<F: FnOnce(EntityBuilder) -> EntityBuilder>(mut self, f: F) -> Doc {
+        let current = self.entities.pop().expect("Doc::entity must be called before describing an entity");
+        self.entities.push(f(current));
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct EntityBuilder {
+    main_type: String,
+    sub_type: Option<String>,
+    identifier: Option<String>,
+    reference: Option<String>,
+    fields: Vec<(String, ExprBuilder)>,
+    children: Vec<EntityBuilder>,
+}
+
+impl EntityBuilder {
+    pub fn sub_type(mut self, sub_type: &str) -> EntityBuilder {
+        self.sub_type = Some(sub_type.to_string());
+        self
+    }
+
+    pub fn id(mut self, identifier: &str) -> EntityBuilder {
+        self.identifier = Some(identifier.to_string());
+        self
+    }
+
+    pub fn reference(mut self, reference: &str) -> EntityBuilder {
+        self.reference = Some(reference.to_string());
+        self
+    }
+
+    pub fn field(mut self, identifier: &str, value: ExprBuilder) -> EntityBuilder {
+        self.fields.push((identifier.to_string(), value));
+        self
+    }
+
+    pub fn child(mut self, child: EntityBuilder) -> EntityBuilder {
+        self.children.push(child);
+        self
+    }
+
+    /// Adds the entity to an existing document without attaching it, see `ParseResult::insert_child`.
+    pub fn add_to(self, pr: &mut ParseResult) -> EntityRef {
+        let mut fields = Vec::new();
+        for (identifier, value) in self.fields {
+            let value = value.add_to(pr);
+            fields.push(pr.add_field(AstFieldNode { identifier, value }));
+        }
+        let children = self.children.into_iter().map(|c| c.add_to(pr)).collect();
+        pr.add_entity(AstEntityNode {
+            main_type: self.main_type,
+            sub_type: self.sub_type,
+            reference: self.reference,
+            identifier: self.identifier,
+            fields,
+            children,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ExprBuilder {
+    String(String),
+    Identifier(String),
+    Number(f64, String),
+    Reference(String),
+    VPath(AstVPathNode),
+    Function(String, Vec<ExprBuilder>),
+    Operator(char, Box<ExprBuilder>, Box<ExprBuilder>),
+    UnaryOperator(char, Box<ExprBuilder>),
+}
+
+impl ExprBuilder {
+    /// Adds the expression and its sub expressions to a document, operands before the operator as the parser does.
+    pub fn add_to(self, pr: &mut ParseResult) -> EntityExprRef {
+        let node = match self {
+            ExprBuilder::String(value) => Expr::String(Box::new(AstStringNode { value })),
+            ExprBuilder::Identifier(value) => Expr::Identifier(Box::new(AstIdentifierNode { value })),
+            ExprBuilder::Number(value, text_rep) => Expr::Number(Box::new(AstNumberNode::new(value, text_rep))),
+            ExprBuilder::Reference(value) => Expr::Reference(Box::new(AstReferenceNode { value })),
+            ExprBuilder::VPath(node) => Expr::VPath(Box::new(node)),
+            ExprBuilder::Function(identifier, args) => {
+                let argument_list = args.into_iter().map(|a| a.add_to(pr)).collect();
+                Expr::Function(Box::new(AstFunctionNode { identifier, argument_list }))
+            }
+            ExprBuilder::Operator(operator, left, right) => {
+                let left_side = left.add_to(pr);
+                let right_side = right.add_to(pr);
+                Expr::Operator(Box::new(AstOperatorNode { operator, left_side, right_side }))
+            }
+            ExprBuilder::UnaryOperator(operator, expr) => {
+                let expr = expr.add_to(pr);
+                Expr::UnaryOperator(Box::new(AstUnaryOperatorNode { operator, expr }))
+            }
+        };
+        pr.add_expr(node)
+    }
+}
+
+pub fn entity(main_type: &str) -> EntityBuilder {
+    EntityBuilder {
+        main_type: main_type.to_string(),
+        sub_type: None,
+        identifier: None,
+        reference: None,
+        fields: Vec::new(),
+        children: Vec::new(),
+    }
+}
+
+pub fn page() -> EntityBuilder {
+    entity("page")
+}
+
+pub fn widget(sub_type: &str) -> EntityBuilder {
+    entity("widget").sub_type(sub_type)
+}
+
+pub fn str(value: &str) -> ExprBuilder {
+    ExprBuilder::String(value.to_string())
+}
+
+pub fn ident(value: &str) -> ExprBuilder {
+    ExprBuilder::Identifier(value.to_string())
+}
+
+/// Negative numbers become a unary minus, which is how the parser reads them.
+pub fn num(value: f64) -> ExprBuilder {
+    if value < 0.0 {
+        return neg(num(-value));
+    }
+    ExprBuilder::Number(value, value.to_string())
+}
+
+pub fn reference(value: &str) -> ExprBuilder {
+    ExprBuilder::Reference(value.to_string())
+}
+
+pub fn vpath(table: &str, field: &str) -> ExprBuilder {
+    ExprBuilder::VPath(AstVPathNode {
+        table: Some(table.to_string()),
+        sub_table: None,
+        field: Some(field.to_string()),
+        sub_field: None,
+    })
+}
+
+pub fn func(identifier: &str, args: Vec<ExprBuilder>) -> ExprBuilder {
+    ExprBuilder::Function(identifier.to_string(), args)
+}
+
+pub fn op(left: ExprBuilder, operator: char, right: ExprBuilder) -> ExprBuilder {
+    ExprBuilder::Operator(operator, Box::new(left), Box::new(right))
+}
+
+pub fn add(left: ExprBuilder, right: ExprBuilder) -> ExprBuilder {
+    op(left, '+', right)
+}
+
+pub fn sub(left: ExprBuilder, right: ExprBuilder) -> ExprBuilder {
+    op(left, '-', right)
+}
+
+pub fn mul(left: ExprBuilder, right: ExprBuilder) -> ExprBuilder {
+    op(left, '*', right)
+}
+
+pub fn div(left: ExprBuilder, right: ExprBuilder) -> ExprBuilder {
+    op(left, '/', right)
+}
+
+pub fn neg(expr: ExprBuilder) -> ExprBuilder {
+    ExprBuilder::UnaryOperator('-', Box::new(expr))
+}
+
+#[cfg(test)]
+mod test {
+    use build::*;
+    use lex::Lexer;
+    use parse::Parser;
+    use print;
+
+    #[test]
+    fn build_matches_parser() {
+        let built = Doc::new()
+            .entity("datatable").sub_type("kpi").id("data1")
+            .field("type", ident("nps"))
+            .field("vpath", vpath("t1", "q1"))
+            .entity("page").id("overview")
+            .child(widget("kpi").id("kpi1").reference("default")
+                .field("label", str("KPI"))
+                .field("value", average_score())
+                .field("offset", add(num(-1.0), mul(num(2.5), ident("x")))))
+            .child(page().child(entity("tile")))
+            .build();
+
+        let printed = print::print(built);
+        let cdl = "datatable kpi #data1 {
+    type: nps
+    vpath: t1:q1
+}
+page #overview {
+    widget kpi #kpi1 @default {
+        label: \"KPI\"
+        value: average(score(survey:Q7), @cr.currentPeriodB2b)
+        offset: -1 + 2.5 * x
+    }
+    page {
+        tile {
+        }
+    }
+}
+";
+        assert_eq!(printed, cdl);
+    }
+
+    #[test]
+    fn build_same_arena_as_parser() {
+        let cdl = "widget kpi {
+    value: average(score(survey:Q7), @cr.currentPeriodB2b) / -(1 - x)
+}
+";
+        let parsed = Parser::new(Lexer::new(cdl.to_string()).lex().unwrap()).parse().unwrap();
+        let built = Doc::new()
+            .add_entity(widget("kpi").field("value", div(average_score(), neg(sub(num(1.0), ident("x"))))))
+            .build();
+        assert_eq!(format!("{:?}", built), format!("{:?}", parsed));
+    }
+
+    #[test]
+    fn build_print_and_parse_again() {
+        // an id without a sub type is printed as `page #overview`, which must not read back as a sub type
+        let build = || Doc::new()
+            .entity("page").id("overview")
+            .child(widget("kpi").id("kpi1").field("label", str("KPI")))
+            .add_entity(entity("tile").id("t1").reference("default"))
+            .build();
+        let printed = print::print(build());
+        assert!(printed.starts_with("page #overview {\n"));
+        let parsed = Parser::new(Lexer::new(printed).lex().unwrap()).parse().unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", build()));
+    }
+
+    fn average_score() -> ExprBuilder {
+        func("average", vec![func("score", vec![vpath("survey", "Q7")]), reference("cr.currentPeriodB2b")])
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use lex::{Lexer, Span};
+use parse::{EntityExprRef, EntityFieldRef, EntityRef, NodeRef, ParseResult, Parser};
+use print;
+use structural::{structural_hash, StructuralOptions};
+
+/// The source text of a parsed script, with where every entity and field came from.
+///
+/// Printing an unmodified `ParseResult` gives back the source byte for byte, comments, blank lines,
+/// quotes and spacing included. After editing the `ParseResult` only the nodes that changed are printed
+/// again, everything else is copied from the source. The arena indexes must stay the ones the parser
+/// gave out, so `compact` should not be called on a document printed this way.
+#[derive(Debug)]
+pub struct Cst {
+    source: String,
+    // end of the last root entity, what comes after it is the tail of the file
+    roots_end: usize,
+    entities: HashMap<EntityRef, EntitySource>,
+    fields: HashMap<EntityFieldRef, FieldSource>,
+}
+
+// `leading_start..start` is the whitespace and comments in front of a node, it goes with the node
+#[derive(Debug)]
+struct EntitySource {
+    parent: Option<EntityRef>,
+    leading_start: usize,
+    start: usize,
+    open_end: usize,
+    items_end: usize,
+    end: usize,
+    header: String,
+    fields: Vec<EntityFieldRef>,
+    children: Vec<EntityRef>,
+    // fields and children in the order they were written
+    order: Vec<NodeRef>,
+    child_indent: Option<String>,
+}
+
+#[derive(Debug)]
+struct FieldSource {
+    owner: EntityRef,
+    leading_start: usize,
+    start: usize,
+    value_start: usize,
+    end: usize,
+    identifier: String,
+    value: EntityExprRef,
+    value_hash: u64,
+}
+
+impl Cst {
+    pub fn parse(source: &str) -> Result<(Cst, ParseResult), String> {
+        let lexer = Lexer::new(source.to_string());
+        let (lex_items, spans): (Vec<_>, Vec<Span>) = lexer.lex_with_spans()?.into_iter().unzip();
+        let parser = Parser::new(lex_items);
+        let (pr, ranges) = parser.parse_with_ranges()?;
+
+        let mut cst = Cst {
+            source: source.to_string(),
+            roots_end: 0,
+            entities: HashMap::new(),
+            fields: HashMap::new(),
+        };
+        for &(field_ref, start, value_start, end) in &ranges.fields {
+            let field = pr.get_field(field_ref);
+            cst.fields.insert(field_ref, FieldSource {
+                owner: 0,
+                leading_start: 0,
+                start: spans[start].start,
+                value_start: spans[value_start].start,
+                end: spans[end - 1].end,
+                identifier: field.identifier.clone(),
+                value: field.value,
+                value_hash: structural_hash(&pr, NodeRef::Expr(field.value), StructuralOptions::default()),
+            });
+        }
+        for &(entity_ref, start, open, end) in &ranges.entities {
+            let entity = pr.get_entity(entity_ref);
+            cst.entities.insert(entity_ref, EntitySource {
+                parent: None,
+                leading_start: 0,
+                start: spans[start].start,
+                open_end: spans[open].end,
+                items_end: spans[open].end,
+                end: spans[end - 1].end,
+                header: print::print_entity_header(entity, 0),
+                fields: entity.fields.clone(),
+                children: entity.children.clone(),
+                order: Vec::new(),
+                child_indent: None,
+            });
+        }
+
+        let mut roots_end = 0;
+        for root_child in &pr.root.children {
+            roots_end = cst.link_entity(&pr, *root_child, None, roots_end);
+        }
+        cst.roots_end = roots_end;
+        Ok((cst, pr))
+    }
+
+    pub fn print(&self, pr: &ParseResult) -> String {
+        // new entities are indented the way the script already is
+        let unit = self.entities.values()
+            .filter(|s| s.parent.is_none())
+            .filter_map(|s| s.child_indent.clone())
+            .next()
+            .unwrap_or_else(|| "    ".to_string());
+        let mut out = String::new();
+        for root_child in &pr.root.children {
+            self.write_item(&mut out, NodeRef::Entity(*root_child), None, "", &unit, pr);
+        }
+        out.push_str(&self.source[self.roots_end..]);
+        out
+    }
+
+    // fills in parents, leading trivia and source order, returns where the entity ends
+    fn link_entity(&mut self, pr: &ParseResult, entity: EntityRef, parent: Option<EntityRef>, leading_start: usize) -> usize {
+        let (open_end, end) = {
+            let source = self.entities.get_mut(&entity).unwrap();
+            source.parent = parent;
+            source.leading_start = leading_start;
+            (source.open_end, source.end)
+        };
+
+        let node = pr.get_entity(entity);
+        let mut items: Vec<(usize, NodeRef)> = Vec::new();
+        for field in &node.fields {
+            items.push((self.fields[field].start, NodeRef::Field(*field)));
+        }
+        for child in &node.children {
+            items.push((self.entities[child].start, NodeRef::Entity(*child)));
+        }
+        items.sort_by_key(|&(start, _)| start);
+
+        let mut prev_end = open_end;
+        for &(_, item) in &items {
+            prev_end = match item {
+                NodeRef::Field(f) => {
+                    let source = self.fields.get_mut(&f).unwrap();
+                    source.owner = entity;
+                    source.leading_start = prev_end;
+                    source.end
+                }
+                NodeRef::Entity(e) => self.link_entity(pr, e, Some(entity), prev_end),
+                NodeRef::Expr(_) => unreachable!(),
+            };
+        }
+
+        let child_indent = items.first().map(|&(start, _)| {
+            let leading = &self.source[open_end..start];
+            leading[leading.rfind('\n').map_or(0, |i| i + 1)..].to_string()
+        });
+        let source = self.entities.get_mut(&entity).unwrap();
+        source.items_end = prev_end;
+        source.order = items.into_iter().map(|(_, item)| item).collect();
+        source.child_indent = child_indent;
+        end
+    }
+
+    fn original_entity(&self, entity: EntityRef, parent: Option<EntityRef>) -> Option<&EntitySource> {
+        self.entities.get(&entity).filter(|s| s.parent == parent)
+    }
+
+    fn original_field(&self, field: EntityFieldRef, owner: EntityRef) -> Option<&FieldSource> {
+        self.fields.get(&field).filter(|s| s.owner == owner)
+    }
+
+    fn write_item(&self, out: &mut String, item: NodeRef, parent: Option<EntityRef>, indent: &str, unit: &str, pr: &ParseResult) {
+        let leading = match (item, parent) {
+            (NodeRef::Entity(e), _) => self.original_entity(e, parent).map(|s| &self.source[s.leading_start..s.start]),
+            (NodeRef::Field(f), Some(owner)) => self.original_field(f, owner).map(|s| &self.source[s.leading_start..s.start]),
+            _ => None,
+        };
+        match leading {
+            // something new was written in front of the node, keep them on separate lines
+            Some(leading) if !out.is_empty() && !out.ends_with('\n') && !leading.contains('\n') => {
+                out.push('\n');
+                out.push_str(indent);
+            }
+            Some(leading) => out.push_str(leading),
+            None => {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                out.push_str(indent);
+            }
+        }
+        match item {
+            NodeRef::Entity(e) => self.write_entity(out, e, parent, indent, unit, pr),
+            NodeRef::Field(f) => self.write_field(out, f, parent.unwrap(), pr),
+            NodeRef::Expr(_) => {}
+        }
+    }
+
+    fn write_entity(&self, out: &mut String, entity: EntityRef, parent: Option<EntityRef>, indent: &str, unit: &str, pr: &ParseResult) {
+        let node = pr.get_entity(entity);
+        let source = match self.original_entity(entity, parent) {
+            Some(source) => source,
+            None => {
+                let printed = print::print_entity(node, 0, pr);
+                out.push_str(&reindent(printed.trim_end_matches('\n'), indent, unit));
+                return;
+            }
+        };
+
+        let header = print::print_entity_header(node, 0);
+        if header == source.header {
+            out.push_str(&self.source[source.start..source.open_end]);
+        } else {
+            out.push_str(&header);
+            out.push('{');
+        }
+
+        let items = if node.fields == source.fields && node.children == source.children {
+            source.order.clone()
+        } else {
+            node.fields.iter().map(|f| NodeRef::Field(*f))
+                .chain(node.children.iter().map(|c| NodeRef::Entity(*c)))
+                .collect()
+        };
+        let child_indent = match source.child_indent {
+            Some(ref child_indent) => child_indent.to_string(),
+            None => format!("{}{}", indent, unit),
+        };
+        let unit = if child_indent.len() > indent.len() && child_indent.starts_with(indent) {
+            &child_indent[indent.len()..]
+        } else {
+            unit
+        };
+        for item in items {
+            self.write_item(out, item, Some(entity), &child_indent, unit, pr);
+        }
+        out.push_str(&self.source[source.items_end..source.end]);
+    }
+
+    fn write_field(&self, out: &mut String, field: EntityFieldRef, owner: EntityRef, pr: &ParseResult) {
+        let node = pr.get_field(field);
+        match self.original_field(field, owner) {
+            Some(source) if source.identifier == node.identifier => {
+                let unchanged = source.value == node.value
+                    && source.value_hash == structural_hash(pr, NodeRef::Expr(node.value), StructuralOptions::default());
+                if unchanged {
+                    out.push_str(&self.source[source.start..source.end]);
+                } else {
+                    out.push_str(&self.source[source.start..source.value_start]);
+                    out.push_str(&print::print_expr(pr.get_expr(node.value), pr));
+                }
+            }
+            _ => out.push_str(print::print_field(node, 0, pr).trim_end_matches('\n')),
+        }
+    }
+}
+
+// moves text from the printer, which indents with four spaces, to `indent` and indents with `unit`
+fn reindent(text: &str, indent: &str, unit: &str) -> String {
+    let lines: Vec<String> = text.split('\n').map(|line| {
+        let trimmed = line.trim_start_matches(' ');
+        let levels = (line.len() - trimmed.len()) / 4;
+        format!("{}{}", unit.repeat(levels), trimmed)
+    }).collect();
+    lines.join(&format!("\n{}", indent))
+}
+
+#[cfg(test)]
+mod test {
+    use build::{entity, ident, str};
+    use cst::Cst;
+    use parse::{AstIdentifierNode, Expr};
+
+    const CDL: &str = "// report settings
+config report cr {
+  logo :   'logo.png'
+
+  // formatters
+  formatter number formatterLTR {
+    numberDecimals : 1
+  }
+  formatter number formatterRR {
+    postfix : \"%\"
+  }
+}
+
+page #overview {
+  widget kpi #kpi1 {
+    label:'KPI'   // inline comment
+    value :average( score(survey:Q7) )
+  }
+}
+";
+
+    #[test]
+    fn unmodified_prints_byte_for_byte() {
+        let (cst, pr) = Cst::parse(CDL).unwrap();
+        assert_eq!(cst.print(&pr), CDL);
+
+        let script = include_str!("../../cdl-scripts/simple.cdl");
+        let (cst, pr) = Cst::parse(script).unwrap();
+        assert_eq!(cst.print(&pr), script);
+    }
+
+    #[test]
+    fn edits_only_touch_changed_nodes() {
+        let (cst, mut pr) = Cst::parse(CDL).unwrap();
+        let config = pr.root.children[0];
+        let page = pr.root.children[1];
+        let formatter_rr = pr.get_entity(config).children[1];
+        let kpi1 = pr.get_entity(page).children[0];
+
+        let value = pr.add_expr(Expr::Identifier(Box::new(AstIdentifierNode { value: "x".to_string() })));
+        pr.set_field(kpi1, "value", value).unwrap();
+        pr.remove_entity(formatter_rr).unwrap();
+        let format = ident("formatterLTR").add_to(&mut pr);
+        pr.set_field(kpi1, "format", format).unwrap();
+        let tile = entity("tile").field("label", str("Tile")).add_to(&mut pr);
+        pr.insert_child(Some(page), 1, tile).unwrap();
+        pr.entities[kpi1].identifier = Some("kpi2".to_string());
+
+        assert_eq!(cst.print(&pr), "// report settings
+config report cr {
+  logo :   'logo.png'
+
+  // formatters
+  formatter number formatterLTR {
+    numberDecimals : 1
+  }
+}
+
+page #overview {
+  widget kpi #kpi2 {
+    label:'KPI'   // inline comment
+    value :x
+    format: formatterLTR
+  }
+  tile {
+    label: \"Tile\"
+  }
+}
+");
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::{HashMap, HashSet};
+use edit::expr_children;
+use parse::{AstEntityNode, EntityExprRef, EntityRef, Expr, ParseResult};
+use print;
+
+// Generates a readable description of a dashboard: its pages and widgets with their labels, formulas and
+// columns, the formatters and definitions of `config report`, and the tables and relations of
+// `config hub`. References like `@cr.ltrValue` link to where they are defined, which is a field of the
+// `config` entity with that id.
+//
+// The document is built up as headings, paragraphs and tables first, which are then written as
+// Markdown or HTML.
+
+enum Inline {
+    Text(String),
+    Code(String),
+    Link(String, String),
+    Anchor(String),
+}
+
+enum Block {
+    Heading(usize, String, Option<String>),
+    Paragraph(Vec<Inline>),
+    Table(Vec<&'static str>, Vec<Vec<Vec<Inline>>>),
+}
+
+/// Describes the dashboard as Markdown.
+pub fn dashboard_markdown(pr: &ParseResult) -> String {
+    let mut res = String::new();
+    for block in Generator::new(pr).generate() {
+        match block {
+            Block::Heading(level, text, anchor) => {
+                res.push_str(&"#".repeat(level));
+                res.push(' ');
+                if let Some(anchor) = anchor {
+                    res.push_str(&markdown_inline(&Inline::Anchor(anchor)));
+                }
+                res.push_str(&escape_markdown(&text));
+                res.push_str("\n\n");
+            }
+            Block::Paragraph(inlines) => {
+                res.push_str(&markdown_inlines(&inlines));
+                res.push_str("\n\n");
+            }
+            Block::Table(headers, rows) => {
+                res.push_str(&format!("| {} |\n", headers.join(" | ")));
+                res.push_str(&format!("|{}\n", " --- |".repeat(headers.len())));
+                for row in rows {
+                    let cells: Vec<String> = row.iter().map(|cell| markdown_cell(cell)).collect();
+                    res.push_str(&format!("| {} |\n", cells.join(" | ")));
+                }
+                res.push('\n');
+            }
+        }
+    }
+    res
+}
+
+/// Describes the dashboard as an HTML fragment.
+pub fn dashboard_html(pr: &ParseResult) -> String {
+    let mut res = String::new();
+    for block in Generator::new(pr).generate() {
+        match block {
+            Block::Heading(level, text, anchor) => {
+                match anchor {
+                    Some(anchor) => res.push_str(&format!("<h{} id=\"{}\">", level, anchor)),
+                    None => res.push_str(&format!("<h{}>", level)),
+                }
+                res.push_str(&escape_html(&text));
+                res.push_str(&format!("</h{}>\n", level));
+            }
+            Block::Paragraph(inlines) => res.push_str(&format!("<p>{}</p>\n", html_inlines(&inlines))),
+            Block::Table(headers, rows) => {
+                res.push_str("<table>\n<thead><tr>");
+                for header in headers {
+                    res.push_str(&format!("<th>{}</th>", header));
+                }
+                res.push_str("</tr></thead>\n<tbody>\n");
+                for row in rows {
+                    res.push_str("<tr>");
+                    for cell in row {
+                        res.push_str(&format!("<td>{}</td>", html_inlines(&cell)));
+                    }
+                    res.push_str("</tr>\n");
+                }
+                res.push_str("</tbody>\n</table>\n");
+            }
+        }
+    }
+    res
+}
+
+struct Generator<'a> {
+    pr: &'a ParseResult,
+    // references that have a definition, like `cr.ltrValue`
+    definitions: HashSet<String>,
+    // anchors given out so far, repeats get a counter suffix
+    anchors: HashSet<String>,
+    // the anchor of each formatter, and of the first formatter with each name for `format` links
+    formatter_anchors: HashMap<EntityRef, String>,
+    formatters: HashMap<String, String>,
+    blocks: Vec<Block>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(pr: &'a ParseResult) -> Generator<'a> {
+        let mut definitions = HashSet::new();
+        let mut anchors: HashSet<String> = ["formatters", "data"].iter().map(|a| a.to_string()).collect();
+        for config in configs(pr, None) {
+            if let Some(ref id) = pr.get_entity(config).identifier {
+                for field in &pr.get_entity(config).fields {
+                    let identifier = &pr.get_field(*field).identifier;
+                    definitions.insert(format!("{}.{}", id, identifier));
+                    anchors.insert(anchor(&[id, identifier]));
+                }
+            }
+        }
+        let mut formatter_anchors = HashMap::new();
+        let mut formatters = HashMap::new();
+        for report in configs(pr, Some("report")) {
+            for formatter in &pr.get_entity(report).children {
+                let node = pr.get_entity(*formatter);
+                if node.main_type == "formatter" {
+                    let formatter_anchor = unique_anchor(&mut anchors, anchor(&["formatter", name(node)]));
+                    formatters.entry(name(node).to_string()).or_insert_with(|| formatter_anchor.clone());
+                    formatter_anchors.insert(*formatter, formatter_anchor);
+                }
+            }
+        }
+        Generator {
+            pr,
+            definitions,
+            anchors,
+            formatter_anchors,
+            formatters,
+            blocks: Vec::new(),
+        }
+    }
+
+    fn generate(mut self) -> Vec<Block> {
+        self.blocks.push(Block::Heading(1, "Dashboard".to_string(), None));
+        let pr = self.pr;
+        for page in descendants(pr, &pr.root.children, "page") {
+            self.page(page);
+        }
+        for report in configs(pr, Some("report")) {
+            self.report(report);
+        }
+        for hub in configs(pr, Some("hub")) {
+            self.hub(hub);
+        }
+        self.blocks
+    }
+
+    fn page(&mut self, page: EntityRef) {
+        let node = self.pr.get_entity(page);
+        let title = format!("Page {}", name(node));
+        let anchor = unique_anchor(&mut self.anchors, anchor(&["page", name(node)]));
+        self.blocks.push(Block::Heading(2, title.trim_end().to_string(), Some(anchor)));
+        let widgets = descendants(self.pr, &node.children, "widget");
+        if widgets.is_empty() {
+            self.blocks.push(Block::Paragraph(vec![Inline::Text("No widgets.".to_string())]));
+        }
+        for widget in widgets {
+            self.widget(widget);
+        }
+    }
+
+    fn widget(&mut self, widget: EntityRef) {
+        let pr = self.pr;
+        let node = pr.get_entity(widget);
+        let mut title = "Widget".to_string();
+        if let Some(ref sub_type) = node.sub_type {
+            title.push_str(&format!(" {}", sub_type));
+        }
+        if let Some(ref identifier) = node.identifier {
+            title.push_str(&format!(" #{}", identifier));
+        }
+        self.blocks.push(Block::Heading(3, title, None));
+
+        let mut formulas = Vec::new();
+        for field in &node.fields {
+            let field = pr.get_field(*field);
+            if field.identifier == "label" {
+                let label = format!("Label: {}", text_value(pr, field.value));
+                self.blocks.push(Block::Paragraph(vec![Inline::Text(label)]));
+            } else {
+                formulas.push(vec![vec![Inline::Code(field.identifier.clone())], self.formula(field.value)]);
+            }
+        }
+        if !formulas.is_empty() {
+            self.blocks.push(Block::Table(vec!["Metric", "Formula"], formulas));
+        }
+
+        let columns: Vec<Vec<Vec<Inline>>> = node.children.iter()
+            .map(|c| pr.get_entity(*c))
+            .filter(|c| c.main_type == "column")
+            .map(|column| {
+                vec![
+                    vec![Inline::Text(column.identifier.clone().unwrap_or_default())],
+                    vec![Inline::Text(column.sub_type.clone().unwrap_or_default())],
+                    self.field_text(column, "label"),
+                    field(pr, column, "value").map_or_else(Vec::new, |value| self.formula(value)),
+                    match field(pr, column, "format").map(|format| text_value(pr, format)) {
+                        Some(format) => match self.formatters.get(&format) {
+                            Some(formatter_anchor) => vec![Inline::Link(format, formatter_anchor.to_string())],
+                            None => vec![Inline::Text(format)],
+                        },
+                        None => Vec::new(),
+                    },
+                ]
+            })
+            .collect();
+        if !columns.is_empty() {
+            self.blocks.push(Block::Table(vec!["Column", "Type", "Label", "Formula", "Format"], columns));
+        }
+    }
+
+    fn report(&mut self, report: EntityRef) {
+        let pr = self.pr;
+        let node = pr.get_entity(report);
+        let formatters: Vec<Vec<Vec<Inline>>> = node.children.iter()
+            .filter_map(|c| self.formatter_anchors.get(c).map(|a| (pr.get_entity(*c), a)))
+            .map(|(formatter, formatter_anchor)| {
+                vec![
+                    vec![Inline::Anchor(formatter_anchor.to_string()), Inline::Code(name(formatter).to_string())],
+                    vec![Inline::Text(formatter.sub_type.clone().unwrap_or_default())],
+                    vec![Inline::Text(settings(pr, formatter))],
+                ]
+            })
+            .collect();
+        if !formatters.is_empty() {
+            self.blocks.push(Block::Heading(2, "Formatters".to_string(), Some("formatters".to_string())));
+            self.blocks.push(Block::Table(vec!["Formatter", "Type", "Settings"], formatters));
+        }
+
+        if let Some(ref id) = node.identifier {
+            let definitions: Vec<Vec<Vec<Inline>>> = node.fields.iter()
+                .map(|f| pr.get_field(*f))
+                .map(|field| {
+                    let reference = format!("{}.{}", id, field.identifier);
+                    vec![
+                        vec![Inline::Anchor(anchor(&[id, &field.identifier])), Inline::Code(format!("@{}", reference))],
+                        self.formula(field.value),
+                    ]
+                })
+                .collect();
+            if !definitions.is_empty() {
+                self.blocks.push(Block::Heading(2, "Definitions".to_string(), None));
+                self.blocks.push(Block::Table(vec!["Reference", "Formula"], definitions));
+            }
+        }
+    }
+
+    fn hub(&mut self, hub: EntityRef) {
+        let pr = self.pr;
+        let node = pr.get_entity(hub);
+        self.blocks.push(Block::Heading(2, "Data".to_string(), Some("data".to_string())));
+        for (main_type, heading, headers) in [("table", "Tables", vec!["Table", "Settings"]),
+                                              ("relation", "Relations", vec!["Relation", "Type", "Settings"])] {
+            let rows: Vec<Vec<Vec<Inline>>> = node.children.iter()
+                .map(|c| pr.get_entity(*c))
+                .filter(|c| c.main_type == main_type)
+                .map(|entity| {
+                    let mut row = vec![vec![Inline::Code(name(entity).to_string())]];
+                    if main_type == "relation" {
+                        row.push(vec![Inline::Text(entity.sub_type.clone().unwrap_or_default())]);
+                    }
+                    row.push(vec![Inline::Text(settings(pr, entity))]);
+                    row
+                })
+                .collect();
+            if !rows.is_empty() {
+                self.blocks.push(Block::Heading(3, heading.to_string(), None));
+                self.blocks.push(Block::Table(headers, rows));
+            }
+        }
+    }
+
+    // the printed formula, followed by links to the definitions it refers to
+    fn formula(&self, expr: EntityExprRef) -> Vec<Inline> {
+        let mut res = vec![Inline::Code(print::print_expr(self.pr.get_expr(expr), self.pr))];
+        let mut links = Vec::new();
+        for reference in references(self.pr, expr) {
+            if self.definitions.contains(&reference) {
+                let parts: Vec<&str> = reference.splitn(2, '.').collect();
+                links.push(Inline::Link(format!("@{}", reference), anchor(&parts)));
+            }
+        }
+        if !links.is_empty() {
+            res.push(Inline::Text(" (".to_string()));
+            for (i, link) in links.into_iter().enumerate() {
+                if i > 0 {
+                    res.push(Inline::Text(", ".to_string()));
+                }
+                res.push(link);
+            }
+            res.push(Inline::Text(")".to_string()));
+        }
+        res
+    }
+
+    fn field_text(&self, entity: &AstEntityNode, identifier: &str) -> Vec<Inline> {
+        match field(self.pr, entity, identifier) {
+            Some(value) => vec![Inline::Text(text_value(self.pr, value))],
+            None => Vec::new(),
+        }
+    }
+}
+
+// entities of `main_type` in these subtrees, in document order
+fn descendants(pr: &ParseResult, entities: &[EntityRef], main_type: &str) -> Vec<EntityRef> {
+    let mut res = Vec::new();
+    for entity in entities {
+        let node = pr.get_entity(*entity);
+        if node.main_type == main_type {
+            res.push(*entity);
+        }
+        res.extend(descendants(pr, &node.children, main_type));
+    }
+    res
+}
+
+fn configs(pr: &ParseResult, sub_type: Option<&str>) -> Vec<EntityRef> {
+    pr.root.children.iter()
+        .cloned()
+        .filter(|c| {
+            let node = pr.get_entity(*c);
+            node.main_type == "config" && (sub_type.is_none() || node.sub_type.as_deref() == sub_type)
+        })
+        .collect()
+}
+
+fn field(pr: &ParseResult, entity: &AstEntityNode, identifier: &str) -> Option<EntityExprRef> {
+    entity.fields.iter()
+        .map(|f| pr.get_field(*f))
+        .find(|f| f.identifier == identifier)
+        .map(|f| f.value)
+}
+
+// an entity's id, or its sub type for entities like `page overview` that have none
+fn name(entity: &AstEntityNode) -> &str {
+    entity.identifier.as_ref().or(entity.sub_type.as_ref()).map_or("", |s| s.as_str())
+}
+
+// strings without their quotes, anything else as CDL
+fn text_value(pr: &ParseResult, expr: EntityExprRef) -> String {
+    match pr.get_expr(expr) {
+        Expr::String(node) => node.value.clone(),
+        expr => print::print_expr(expr, pr),
+    }
+}
+
+fn settings(pr: &ParseResult, entity: &AstEntityNode) -> String {
+    let fields: Vec<String> = entity.fields.iter()
+        .map(|f| pr.get_field(*f))
+        .map(|f| format!("{}: {}", f.identifier, print::print_expr(pr.get_expr(f.value), pr)))
+        .collect();
+    fields.join(", ")
+}
+
+// the references in an expression, in order and without repeats
+fn references(pr: &ParseResult, expr: EntityExprRef) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        let node = pr.get_expr(expr);
+        if let Expr::Reference(reference) = node {
+            if !res.contains(&reference.value) {
+                res.push(reference.value.clone());
+            }
+        }
+        stack.extend(expr_children(node).into_iter().rev());
+    }
+    res
+}
+
+fn anchor(parts: &[&str]) -> String {
+    let joined = parts.join("-");
+    joined.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '-' }).collect()
+}
+
+// the anchor, or when it is taken the anchor with the first free counter, like `page-overview-1`
+fn unique_anchor(anchors: &mut HashSet<String>, anchor: String) -> String {
+    let mut unique = anchor.clone();
+    let mut counter = 0;
+    while !anchors.insert(unique.clone()) {
+        counter += 1;
+        unique = format!("{}-{}", anchor, counter);
+    }
+    unique
+}
+
+fn markdown_inlines(inlines: &[Inline]) -> String {
+    inlines.iter().map(markdown_inline).collect()
+}
+
+fn markdown_inline(inline: &Inline) -> String {
+    match inline {
+        Inline::Text(text) => escape_markdown(&text.replace('\n', " ")),
+        Inline::Code(code) => format!("`{}`", code),
+        Inline::Link(text, anchor) => format!("[{}](#{})", escape_markdown(text), anchor),
+        Inline::Anchor(anchor) => format!("<a id=\"{}\"></a>", anchor),
+    }
+}
+
+// text is escaped already, a `|` in code would still end the cell
+fn markdown_cell(inlines: &[Inline]) -> String {
+    inlines.iter().map(|inline| match inline {
+        Inline::Code(code) => format!("`{}`", code.replace('|', "\\|")),
+        _ => markdown_inline(inline),
+    }).collect()
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '#' | '|' | '[' | ']' | '<' | '>') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+fn html_inlines(inlines: &[Inline]) -> String {
+    inlines.iter().map(|inline| match inline {
+        Inline::Text(text) => escape_html(text),
+        Inline::Code(code) => format!("<code>{}</code>", escape_html(code)),
+        Inline::Link(text, anchor) => format!("<a href=\"#{}\">{}</a>", anchor, escape_html(text)),
+        Inline::Anchor(anchor) => format!("<a id=\"{}\"></a>", anchor),
+    }).collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use docgen::{dashboard_html, dashboard_markdown};
+    use lex::Lexer;
+    use parse::{ParseResult, Parser};
+
+    const CDL: &str = "config hub {
+    hub: 432
+    table accounts {
+        source: crmdata:ArtuAccountHierarchy
+    }
+    relation oneToMany rel1 {
+        primaryKey: accounts:AccountID
+        foreignKey: survey:AccountID
+    }
+}
+config report cr {
+    formatter number formatterLTR {
+        numberDecimals: 1
+    }
+    ltrValue: average(score(survey:Q1))
+    rateValue: @cr.rateResponses / @cr.rateInvites * 100
+}
+page overview {
+    widget kpi #kpi1 {
+        label: \"LTR\"
+        value: @cr.ltrValue
+        target: 9
+    }
+    widget accountList {
+        label: \"Accounts\"
+        column value accountID {
+            label: \"Account ID\"
+            value: accounts:AccountID
+        }
+        column metric osat {
+            label: \"OSAT | score\"
+            value: average(score(survey:Q4), @cr.ltrValue)
+            format: formatterLTR
+        }
+    }
+}
+";
+
+    fn parse(cdl: &str) -> ParseResult {
+        let lexer = Lexer::new(cdl.to_string());
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        parser.parse().unwrap()
+    }
+
+    #[test]
+    fn markdown_document() {
+        assert_eq!(dashboard_markdown(&parse(CDL)), "# Dashboard
+
+## <a id=\"page-overview\"></a>Page overview
+
+### Widget kpi \\#kpi1
+
+Label: LTR
+
+| Metric | Formula |
+| --- | --- |
+| `value` | `@cr.ltrValue` ([@cr.ltrValue](#cr-ltrValue)) |
+| `target` | `9` |
+
+### Widget accountList
+
+Label: Accounts
+
+| Column | Type | Label | Formula | Format |
+| --- | --- | --- | --- | --- |
+| accountID | value | Account ID | `accounts:AccountID` |  |
+| osat | metric | OSAT \\| score | `average(score(survey:Q4), @cr.ltrValue)` ([@cr.ltrValue](#cr-ltrValue)) | [formatterLTR](#formatter-formatterLTR) |
+
+## <a id=\"formatters\"></a>Formatters
+
+| Formatter | Type | Settings |
+| --- | --- | --- |
+| <a id=\"formatter-formatterLTR\"></a>`formatterLTR` | number | numberDecimals: 1 |
+
+## Definitions
+
+| Reference | Formula |
+| --- | --- |
+| <a id=\"cr-ltrValue\"></a>`@cr.ltrValue` | `average(score(survey:Q1))` |
+| <a id=\"cr-rateValue\"></a>`@cr.rateValue` | `@cr.rateResponses / @cr.rateInvites * 100` |
+
+## <a id=\"data\"></a>Data
+
+### Tables
+
+| Table | Settings |
+| --- | --- |
+| `accounts` | source: crmdata:ArtuAccountHierarchy |
+
+### Relations
+
+| Relation | Type | Settings |
+| --- | --- | --- |
+| `rel1` | oneToMany | primaryKey: accounts:AccountID, foreignKey: survey:AccountID |
+
+");
+    }
+
+    #[test]
+    fn html_document() {
+        let html = dashboard_html(&parse(CDL));
+        assert!(html.starts_with("<h1>Dashboard</h1>\n<h2 id=\"page-overview\">Page overview</h2>\n"));
+        assert!(html.contains("<tr><td><code>value</code></td><td><code>@cr.ltrValue</code> \
+(<a href=\"#cr-ltrValue\">@cr.ltrValue</a>)</td></tr>"));
+        assert!(html.contains("<td><a id=\"cr-ltrValue\"></a><code>@cr.ltrValue</code></td>"));
+        assert!(html.contains("<td>OSAT | score</td>"));
+    }
+
+    #[test]
+    fn repeated_names_get_their_own_anchors() {
+        let cdl = "config report cr {
+    formatter number f1 {
+        numberDecimals: 1
+    }
+    formatter number f1 {
+        numberDecimals: 2
+    }
+}
+page #overview {
+    widget list {
+        column metric m1 {
+            format: f1
+        }
+        column metric m2 {
+            format: f2
+        }
+    }
+}
+page #overview {
+}
+page #overview {
+}
+";
+        let markdown = dashboard_markdown(&parse(cdl));
+        assert!(markdown.contains("## <a id=\"page-overview\"></a>Page overview\n"), "{}", markdown);
+        assert!(markdown.contains("## <a id=\"page-overview-1\"></a>Page overview\n"), "{}", markdown);
+        assert!(markdown.contains("## <a id=\"page-overview-2\"></a>Page overview\n"), "{}", markdown);
+        assert!(markdown.contains("| [f1](#formatter-f1) |\n"), "{}", markdown);
+        assert!(markdown.contains("| f2 |\n"), "{}", markdown);
+        assert!(markdown.contains("| <a id=\"formatter-f1\"></a>`f1` | number | numberDecimals: 1 |\n"));
+        assert!(markdown.contains("| <a id=\"formatter-f1-1\"></a>`f1` | number | numberDecimals: 2 |\n"));
+    }
+
+    #[test]
+    fn markdown_escapes_labels() {
+        let cdl = "page #overview {\n    widget kpi #kpi_1 {\n        label: \"*Top* | #1 [x]\"\n    }\n}\n";
+        let markdown = dashboard_markdown(&parse(cdl));
+        assert!(markdown.contains("### Widget kpi \\#kpi\\_1\n"), "{}", markdown);
+        assert!(markdown.contains("Label: \\*Top\\* \\| \\#1 \\[x\\]\n"), "{}", markdown);
+    }
+}
@@ -0,0 +1,396 @@
+use parse::{AstEntityNode, AstFieldNode, EntityExprRef, EntityFieldRef, EntityRef, Expr, ParseResult};
+
+// Editing operations on a ParseResult.
+//
+// Nodes that are removed or replaced are only detached from the tree, their slots stay in the
+// arenas so that every other reference stays valid. Detached slots are never reached when
+// walking from the root, and are dropped by `compact`.
+impl ParseResult {
+    /// Finds the parent of an entity. `Ok(None)` means the entity is a root child.
+    pub fn find_parent(&self, entity: EntityRef) -> Result<Option<EntityRef>, String> {
+        // walk down from the root, entities below a detached one are still its children
+        let mut stack: Vec<(Option<EntityRef>, EntityRef)> = self.root.children.iter().map(|c| (None, *c)).collect();
+        while let Some((parent, current)) = stack.pop() {
+            if current == entity {
+                return Ok(parent);
+            }
+            stack.extend(self.entities[current].children.iter().map(|c| (Some(current), *c)));
+        }
+        Err(format!("Entity {} is not attached to the tree", entity))
+    }
+
+    /// Inserts `child` as a child of `parent` at `position`, `None` as parent means the root.
+    pub fn insert_child(&mut self, parent: Option<EntityRef>, position: usize, child: EntityRef) -> Result<(), String> {
+        self.check_entity(child)?;
+        if let Some(p) = parent {
+            self.check_entity(p)?;
+            if self.is_same_or_ancestor(child, p) {
+                return Err(format!("Cannot insert entity {} inside itself", child));
+            }
+        }
+        if self.find_parent(child).is_ok() {
+            return Err(format!("Entity {} is already attached, use move_entity instead", child));
+        }
+        let children = self.children_mut(parent);
+        if position > children.len() {
+            return Err(format!("Position {} is out of bounds, entity has {} children", position, children.len()));
+        }
+        children.insert(position, child);
+        self.invalidate_index();
+        Ok(())
+    }
+
+    /// Detaches an entity, and everything below it, from the tree.
+    pub fn remove_entity(&mut self, entity: EntityRef) -> Result<(), String> {
+        let parent = self.find_parent(entity)?;
+        self.children_mut(parent).retain(|c| *c != entity);
+        self.invalidate_index();
+        Ok(())
+    }
+
+    /// Moves an attached entity to `position` among the children of `new_parent`.
+    pub fn move_entity(&mut self, entity: EntityRef, new_parent: Option<EntityRef>, position: usize) -> Result<(), String> {
+        if let Some(p) = new_parent {
+            self.check_entity(p)?;
+            if self.is_same_or_ancestor(entity, p) {
+                return Err(format!("Cannot move entity {} inside itself", entity));
+            }
+        }
+        let old_parent = self.find_parent(entity)?;
+        let len = self.children_mut(new_parent).len() - if old_parent == new_parent { 1 } else { 0 };
+        if position > len {
+            return Err(format!("Position {} is out of bounds, entity has {} children", position, len));
+        }
+        self.children_mut(old_parent).retain(|c| *c != entity);
+        self.children_mut(new_parent).insert(position, entity);
+        self.invalidate_index();
+        Ok(())
+    }
+
+    /// Sets the value of the field named `identifier`, adding the field if the entity does not have it.
+    pub fn set_field(&mut self, entity: EntityRef, identifier: &str, value: EntityExprRef) -> Result<EntityFieldRef, String> {
+        self.check_entity(entity)?;
+        self.check_expr(value)?;
+        match self.find_field(entity, identifier) {
+            Some(field_ref) => {
+                self.fields[field_ref].value = value;
+                Ok(field_ref)
+            }
+            None => {
+                let field_ref = self.add_field(AstFieldNode {
+                    identifier: identifier.to_string(),
+                    value,
+                });
+                self.entities[entity].fields.push(field_ref);
+                self.invalidate_index();
+                Ok(field_ref)
+            }
+        }
+    }
+
+    /// Removes the field named `identifier` from an entity, returning the detached field.
+    pub fn remove_field(&mut self, entity: EntityRef, identifier: &str) -> Result<Option<EntityFieldRef>, String> {
+        self.check_entity(entity)?;
+        let found = self.find_field(entity, identifier);
+        if let Some(field_ref) = found {
+            self.entities[entity].fields.retain(|f| *f != field_ref);
+            self.invalidate_index();
+        }
+        Ok(found)
+    }
+
+    /// Replaces the expression in slot `target`, the old subtree is detached.
+    pub fn replace_expr(&mut self, target: EntityExprRef, node: Expr) -> Result<(), String> {
+        self.check_expr(target)?;
+        for child in expr_children(&node) {
+            self.check_expr(child)?;
+            if self.expr_contains(child, target) {
+                return Err(format!("Replacement for expression {} refers back to itself", target));
+            }
+        }
+        self.expressions[target] = node;
+        Ok(())
+    }
+
+    /// Rebuilds the arenas with only the nodes reachable from the root. They are numbered in the order the
+    /// parser adds them, the fields and children of an entity before the entity itself.
+    pub fn compact(&mut self) {
+        self.invalidate_index();
+        let (entity_count, field_count, expr_count) = (self.entities.len(), self.fields.len(), self.expressions.len());
+        let mut compactor = Compactor {
+            entities: self.entities.drain(..).map(Some).collect(),
+            fields: self.fields.drain(..).map(Some).collect(),
+            expressions: self.expressions.drain(..).map(Some).collect(),
+            entity_map: vec![None; entity_count],
+            field_map: vec![None; field_count],
+            expr_map: vec![None; expr_count],
+        };
+        let roots = ::std::mem::take(&mut self.root.children);
+        for root_child in roots {
+            let new_ref = compactor.move_entity(root_child, self);
+            self.root.children.push(new_ref);
+        }
+    }
+
+    fn children_mut(&mut self, parent: Option<EntityRef>) -> &mut Vec<EntityRef> {
+        match parent {
+            Some(p) => &mut self.entities[p].children,
+            None => &mut self.root.children,
+        }
+    }
+
+    fn find_field(&self, entity: EntityRef, identifier: &str) -> Option<EntityFieldRef> {
+        self.entities[entity].fields.iter()
+            .cloned()
+            .find(|f| self.fields[*f].identifier == identifier)
+    }
+
+    fn is_same_or_ancestor(&self, ancestor: EntityRef, entity: EntityRef) -> bool {
+        ancestor == entity || self.entities[ancestor].children.iter().any(|c| self.is_same_or_ancestor(*c, entity))
+    }
+
+    fn expr_contains(&self, expr: EntityExprRef, target: EntityExprRef) -> bool {
+        expr == target || expr_children(&self.expressions[expr]).into_iter().any(|c| self.expr_contains(c, target))
+    }
+
+    fn check_entity(&self, entity: EntityRef) -> Result<(), String> {
+        if entity < self.entities.len() {
+            Ok(())
+        } else {
+            Err(format!("Entity {} does not exist", entity))
+        }
+    }
+
+    fn check_expr(&self, expr: EntityExprRef) -> Result<(), String> {
+        if expr < self.expressions.len() {
+            Ok(())
+        } else {
+            Err(format!("Expression {} does not exist", expr))
+        }
+    }
+}
+
+pub fn expr_children(expr: &Expr) -> Vec<EntityExprRef> {
+    match expr {
+        Expr::Operator(node) => vec![node.left_side, node.right_side],
+        Expr::UnaryOperator(node) => vec![node.expr],
+        Expr::Function(node) => node.argument_list.clone(),
+        _ => vec![],
+    }
+}
+
+struct Compactor {
+    entities: Vec<Option<AstEntityNode>>,
+    fields: Vec<Option<AstFieldNode>>,
+    expressions: Vec<Option<Expr>>,
+    entity_map: Vec<Option<EntityRef>>,
+    field_map: Vec<Option<EntityFieldRef>>,
+    expr_map: Vec<Option<EntityExprRef>>,
+}
+
+impl Compactor {
+    // children are added before their parent, the same order the parser produces
+    fn move_entity(&mut self, old: EntityRef, pr: &mut ParseResult) -> EntityRef {
+        if let Some(new_ref) = self.entity_map[old] {
+            return new_ref;
+        }
+        let mut node = self.entities[old].take().unwrap();
+        node.fields = node.fields.iter().map(|f| self.move_field(*f, pr)).collect();
+        node.children = node.children.iter().map(|c| self.move_entity(*c, pr)).collect();
+        let new_ref = pr.add_entity(node);
+        self.entity_map[old] = Some(new_ref);
+        new_ref
+    }
+
+    fn move_field(&mut self, old: EntityFieldRef, pr: &mut ParseResult) -> EntityFieldRef {
+        if let Some(new_ref) = self.field_map[old] {
+            return new_ref;
+        }
+        let mut node = self.fields[old].take().unwrap();
+        node.value = self.move_expr(node.value, pr);
+        let new_ref = pr.add_field(node);
+        self.field_map[old] = Some(new_ref);
+        new_ref
+    }
+
+    fn move_expr(&mut self, old: EntityExprRef, pr: &mut ParseResult) -> EntityExprRef {
+        if let Some(new_ref) = self.expr_map[old] {
+            return new_ref;
+        }
+        let mut node = self.expressions[old].take().unwrap();
+        match node {
+            Expr::Operator(ref mut op) => {
+                op.left_side = self.move_expr(op.left_side, pr);
+                op.right_side = self.move_expr(op.right_side, pr);
+            }
+            Expr::UnaryOperator(ref mut op) => {
+                op.expr = self.move_expr(op.expr, pr);
+            }
+            Expr::Function(ref mut func) => {
+                func.argument_list = func.argument_list.iter().map(|a| self.move_expr(*a, pr)).collect();
+            }
+            _ => {}
+        }
+        let new_ref = pr.add_expr(node);
+        self.expr_map[old] = Some(new_ref);
+        new_ref
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lex::Lexer;
+    use parse::{AstIdentifierNode, AstNumberNode, AstOperatorNode, Expr, ParseResult, Parser};
+    use print;
+
+    const CDL: &str = "page overview {
+    widget kpi #kpi1 {
+        label: \"KPI\"
+    }
+    widget kpi #kpi2 {
+        label: \"KPI 2\"
+        value: 1 + 2
+    }
+}
+page details {
+}
+";
+
+    fn parse(cdl: &str) -> ParseResult {
+        let lexer = Lexer::new(cdl.to_string());
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        parser.parse().unwrap()
+    }
+
+    fn ident(pr: &mut ParseResult, value: &str) -> usize {
+        pr.add_expr(Expr::Identifier(Box::new(AstIdentifierNode {
+            value: value.to_string()
+        })))
+    }
+
+    #[test]
+    fn set_and_remove_field() {
+        let mut pr = parse(CDL);
+        let kpi1 = pr.root.children[0];
+        let kpi1 = pr.get_entity(kpi1).children[0];
+        let format = ident(&mut pr, "formatterLTR");
+        pr.set_field(kpi1, "format", format).unwrap();
+        let other = ident(&mut pr, "other");
+        pr.set_field(kpi1, "format", other).unwrap();
+        assert_eq!(pr.get_entity(kpi1).fields.len(), 2);
+
+        let removed = pr.remove_field(kpi1, "label").unwrap();
+        assert!(removed.is_some());
+        assert_eq!(pr.remove_field(kpi1, "label").unwrap(), None);
+        assert_eq!(print::print(pr), "page overview {
+    widget kpi #kpi1 {
+        format: other
+    }
+    widget kpi #kpi2 {
+        label: \"KPI 2\"
+        value: 1 + 2
+    }
+}
+page details {
+}
+");
+    }
+
+    #[test]
+    fn remove_and_move_entities() {
+        let mut pr = parse(CDL);
+        let overview = pr.root.children[0];
+        let details = pr.root.children[1];
+        let kpi1 = pr.get_entity(overview).children[0];
+        let kpi2 = pr.get_entity(overview).children[1];
+
+        pr.move_entity(kpi2, Some(details), 0).unwrap();
+        pr.move_entity(details, None, 0).unwrap();
+        pr.remove_entity(kpi1).unwrap();
+        assert!(pr.remove_entity(kpi1).is_err());
+        assert!(pr.move_entity(details, Some(kpi2), 0).is_err());
+
+        pr.insert_child(Some(overview), 0, kpi1).unwrap();
+        assert!(pr.insert_child(Some(overview), 0, kpi1).is_err());
+        assert_eq!(print::print(pr), "page details {
+    widget kpi #kpi2 {
+        label: \"KPI 2\"
+        value: 1 + 2
+    }
+}
+page overview {
+    widget kpi #kpi1 {
+        label: \"KPI\"
+    }
+}
+");
+    }
+
+    #[test]
+    fn entities_below_detached_ones_are_detached() {
+        let mut pr = parse(CDL);
+        let overview = pr.root.children[0];
+        let details = pr.root.children[1];
+        let kpi1 = pr.get_entity(overview).children[0];
+        assert_eq!(pr.find_parent(kpi1), Ok(Some(overview)));
+
+        pr.remove_entity(overview).unwrap();
+        assert!(pr.find_parent(kpi1).is_err());
+        assert!(pr.remove_entity(kpi1).is_err());
+        pr.insert_child(Some(details), 0, kpi1).unwrap();
+        assert_eq!(pr.find_parent(kpi1), Ok(Some(details)));
+        assert_eq!(print::print(pr), "page details {
+    widget kpi #kpi1 {
+        label: \"KPI\"
+    }
+}
+");
+    }
+
+    #[test]
+    fn replace_expression_subtree() {
+        let mut pr = parse(CDL);
+        let kpi2 = pr.get_entity(pr.root.children[0]).children[1];
+        let value = pr.get_field(pr.get_entity(kpi2).fields[1]).value;
+        let left = pr.add_expr(Expr::Number(Box::new(AstNumberNode::new(3.0, "3".to_string()))));
+        let right = ident(&mut pr, "x");
+        pr.replace_expr(value, Expr::Operator(Box::new(AstOperatorNode {
+            operator: '*',
+            left_side: left,
+            right_side: right,
+        }))).unwrap();
+        let cycle = Expr::Operator(Box::new(AstOperatorNode {
+            operator: '+',
+            left_side: value,
+            right_side: right,
+        }));
+        assert!(pr.replace_expr(value, cycle).is_err());
+        assert!(print::print(pr).contains("value: 3 * x"));
+    }
+
+    #[test]
+    fn compact_drops_detached_nodes() {
+        let mut pr = parse(CDL);
+        let overview = pr.root.children[0];
+        let kpi2 = pr.get_entity(overview).children[1];
+        pr.remove_entity(kpi2).unwrap();
+        let unused = ident(&mut pr, "unused");
+        pr.replace_expr(unused, Expr::Number(Box::new(AstNumberNode::new(1.0, "1".to_string())))).unwrap();
+        pr.compact();
+
+        assert_eq!(pr.entities.len(), 3);
+        assert_eq!(pr.fields.len(), 1);
+        assert_eq!(pr.expressions.len(), 1);
+        assert_eq!(pr.root.children, vec![1, 2]);
+        assert_eq!(pr.get_entity(1).children, vec![0]);
+        assert_eq!(print::print(pr), "page overview {
+    widget kpi #kpi1 {
+        label: \"KPI\"
+    }
+}
+page details {
+}
+");
+    }
+}
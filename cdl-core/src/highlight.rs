@@ -0,0 +1,283 @@
+use lex::{LexItem, Lexer, Span};
+
+// Syntax highlighting of CDL source, as HTML or as ANSI coloured text for terminals. Tokens come from
+// the lexer, and are told apart by where they are in the grammar: an identifier at the start of a line
+// is a field name when a colon follows and an entity type otherwise, one followed by a parenthesis is a
+// function, and so on. Whitespace and comments between tokens are kept as they are.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenClass {
+    EntityType,
+    SubType,
+    Id,
+    Reference,
+    Field,
+    String,
+    Number,
+    VPath,
+    Function,
+    Identifier,
+    Operator,
+    Punctuation,
+    Comment,
+    /// whitespace, and characters the lexer skips
+    Plain,
+}
+
+impl TokenClass {
+    pub fn css_class(&self) -> Option<&'static str> {
+        match self {
+            TokenClass::EntityType => Some("cdl-entity-type"),
+            TokenClass::SubType => Some("cdl-sub-type"),
+            TokenClass::Id => Some("cdl-id"),
+            TokenClass::Reference => Some("cdl-reference"),
+            TokenClass::Field => Some("cdl-field"),
+            TokenClass::String => Some("cdl-string"),
+            TokenClass::Number => Some("cdl-number"),
+            TokenClass::VPath => Some("cdl-vpath"),
+            TokenClass::Function => Some("cdl-function"),
+            TokenClass::Identifier => Some("cdl-identifier"),
+            TokenClass::Operator => Some("cdl-operator"),
+            TokenClass::Punctuation => Some("cdl-punctuation"),
+            TokenClass::Comment => Some("cdl-comment"),
+            TokenClass::Plain => None,
+        }
+    }
+
+    pub fn ansi_code(&self) -> Option<&'static str> {
+        match self {
+            TokenClass::EntityType => Some("1;34"),
+            TokenClass::SubType => Some("34"),
+            TokenClass::Id => Some("1;33"),
+            TokenClass::Reference => Some("35"),
+            TokenClass::Field => Some("36"),
+            TokenClass::String => Some("32"),
+            TokenClass::Number => Some("33"),
+            TokenClass::VPath => Some("96"),
+            TokenClass::Function => Some("94"),
+            TokenClass::Operator => Some("31"),
+            TokenClass::Comment => Some("90"),
+            TokenClass::Identifier | TokenClass::Punctuation | TokenClass::Plain => None,
+        }
+    }
+}
+
+/// Splits the source into classified pieces which together are the whole source. Neighbouring tokens
+/// of the same class, like the parts of a vpath, are one piece.
+pub fn highlight(source: &str) -> Result<Vec<(TokenClass, &str)>, String> {
+    let tokens = Lexer::new(source.to_string()).lex_with_spans()?;
+    let classes = classify(&tokens);
+
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+    for (&(_, span), class) in tokens.iter().zip(classes) {
+        push_gap(&mut pieces, source, pos, span.start);
+        push_piece(&mut pieces, class, span.start, span.end);
+        pos = span.end;
+    }
+    push_gap(&mut pieces, source, pos, source.len());
+    Ok(pieces.into_iter().map(|(class, start, end)| (class, &source[start..end])).collect())
+}
+
+/// The `//` comments in the source, which `format` leaves out.
+pub fn comments(source: &str) -> Result<Vec<&str>, String> {
+    let pieces = highlight(source)?;
+    Ok(pieces.into_iter().filter(|&(class, _)| class == TokenClass::Comment).map(|(_, text)| text).collect())
+}
+
+pub fn highlight_html(source: &str) -> Result<String, String> {
+    let mut res = "<pre class=\"cdl\">".to_string();
+    for (class, text) in highlight(source)? {
+        match class.css_class() {
+            Some(css_class) => {
+                res.push_str(&format!("<span class=\"{}\">{}</span>", css_class, escape_html(text)));
+            }
+            None => res.push_str(&escape_html(text)),
+        }
+    }
+    res.push_str("</pre>\n");
+    Ok(res)
+}
+
+pub fn highlight_ansi(source: &str) -> Result<String, String> {
+    let mut res = String::new();
+    for (class, text) in highlight(source)? {
+        match class.ansi_code() {
+            Some(code) => res.push_str(&format!("\x1b[{}m{}\x1b[0m", code, text)),
+            None => res.push_str(text),
+        }
+    }
+    Ok(res)
+}
+
+fn classify(tokens: &[(LexItem, Span)]) -> Vec<TokenClass> {
+    let mut classes = Vec::with_capacity(tokens.len());
+    // identifiers seen so far in an entity header, the parser reads them as type, sub type and id
+    let mut header: Option<usize> = None;
+    let mut i = 0;
+    while i < tokens.len() {
+        let line_start = i == 0 ||
+            matches!(tokens[i - 1].0, LexItem::EOL | LexItem::OpenBracket | LexItem::CloseBracket);
+        let next = tokens.get(i + 1).map(|t| &t.0);
+        let class = match tokens[i].0 {
+            LexItem::Identifier(_) if header == Some(1) => {
+                header = Some(2);
+                TokenClass::SubType
+            }
+            LexItem::Identifier(_) if header.is_some() => TokenClass::Id,
+            LexItem::Identifier(_) if next == Some(&LexItem::OpenPar) => TokenClass::Function,
+            LexItem::Identifier(_) if next == Some(&LexItem::Colon) && line_start => TokenClass::Field,
+            LexItem::Identifier(_) if next == Some(&LexItem::Colon) => {
+                // table:field, all three tokens are the vpath
+                classes.push(TokenClass::VPath);
+                classes.push(TokenClass::VPath);
+                i += 2;
+                TokenClass::VPath
+            }
+            LexItem::Identifier(_) if line_start && opens_entity(&tokens[i..]) => {
+                header = Some(1);
+                TokenClass::EntityType
+            }
+            LexItem::Identifier(_) => TokenClass::Identifier,
+            LexItem::Id(_) => TokenClass::Id,
+            LexItem::String(_) => TokenClass::String,
+            LexItem::Reference(_) => TokenClass::Reference,
+            LexItem::Number { .. } => TokenClass::Number,
+            LexItem::Plus | LexItem::Minus | LexItem::Mul | LexItem::Div | LexItem::Equal | LexItem::LessThan |
+            LexItem::MoreThan | LexItem::Percent => TokenClass::Operator,
+            LexItem::OpenBracket => {
+                header = None;
+                TokenClass::Punctuation
+            }
+            LexItem::Colon | LexItem::Comma | LexItem::CloseBracket | LexItem::OpenPar | LexItem::ClosePar => {
+                TokenClass::Punctuation
+            }
+            LexItem::EOL => TokenClass::Plain,
+        };
+        classes.push(class);
+        i += 1;
+    }
+    classes.truncate(tokens.len());
+    classes
+}
+
+// tells if the line starting with the first token ends in `{`, lines of a field value broken over
+// several lines start with identifiers as well
+fn opens_entity(tokens: &[(LexItem, Span)]) -> bool {
+    tokens.iter()
+        .map(|t| &t.0)
+        .find(|t| matches!(t, LexItem::EOL | LexItem::OpenBracket | LexItem::CloseBracket))
+        == Some(&LexItem::OpenBracket)
+}
+
+fn push_piece(pieces: &mut Vec<(TokenClass, usize, usize)>, class: TokenClass, start: usize, end: usize) {
+    match pieces.last_mut() {
+        Some(last) if last.0 == class && last.2 == start => last.2 = end,
+        _ => pieces.push((class, start, end)),
+    }
+}
+
+// whitespace between tokens, with `//` comments running to the end of their line
+fn push_gap(pieces: &mut Vec<(TokenClass, usize, usize)>, source: &str, start: usize, end: usize) {
+    let mut pos = start;
+    while let Some(comment_start) = source[pos..end].find("//").map(|i| pos + i) {
+        if comment_start > pos {
+            push_piece(pieces, TokenClass::Plain, pos, comment_start);
+        }
+        let comment_end = source[comment_start..end].find('\n').map_or(end, |i| comment_start + i);
+        push_piece(pieces, TokenClass::Comment, comment_start, comment_end);
+        pos = comment_end;
+    }
+    if end > pos {
+        push_piece(pieces, TokenClass::Plain, pos, end);
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use highlight::{comments, highlight, highlight_ansi, highlight_html, TokenClass};
+    use print::PrintOptions;
+
+    const CDL: &str = "// overview
+page #overview {
+    widget kpi #kpi1 @default {
+        label: \"<KPI>\"
+        value: average(score(survey:Q7)) * 100 // percent
+    }
+}
+";
+
+    #[test]
+    fn highlight_classes() {
+        let pieces: Vec<(TokenClass, &str)> = highlight(CDL).unwrap().into_iter()
+            .filter(|&(class, _)| class != TokenClass::Plain && class != TokenClass::Punctuation)
+            .collect();
+        assert_eq!(pieces, vec![
+            (TokenClass::Comment, "// overview"),
+            (TokenClass::EntityType, "page"),
+            (TokenClass::Id, "#overview"),
+            (TokenClass::EntityType, "widget"),
+            (TokenClass::SubType, "kpi"),
+            (TokenClass::Id, "#kpi1"),
+            (TokenClass::Reference, "@default"),
+            (TokenClass::Field, "label"),
+            (TokenClass::String, "\"<KPI>\""),
+            (TokenClass::Field, "value"),
+            (TokenClass::Function, "average"),
+            (TokenClass::Function, "score"),
+            (TokenClass::VPath, "survey:Q7"),
+            (TokenClass::Operator, "*"),
+            (TokenClass::Number, "100"),
+            (TokenClass::Comment, "// percent"),
+        ]);
+
+        let text: String = highlight(CDL).unwrap().into_iter().map(|(_, text)| text).collect();
+        assert_eq!(text, CDL);
+    }
+
+    #[test]
+    fn comments_are_found_outside_strings() {
+        assert_eq!(comments(CDL).unwrap(), vec!["// overview", "// percent"]);
+        assert!(comments("widget kpi {\n    link: \"http://example.com\"\n}\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn highlight_html_and_ansi() {
+        let html = highlight_html("widget kpi {\n    label: \"<KPI>\"\n}\n").unwrap();
+        assert_eq!(html, "<pre class=\"cdl\"><span class=\"cdl-entity-type\">widget</span> \
+<span class=\"cdl-sub-type\">kpi</span> <span class=\"cdl-punctuation\">{</span>
+    <span class=\"cdl-field\">label</span><span class=\"cdl-punctuation\">:</span> \
+<span class=\"cdl-string\">&quot;&lt;KPI&gt;&quot;</span>
+<span class=\"cdl-punctuation\">}</span>
+</pre>
+");
+
+        let pieces = highlight("datatable kpi data1 {\n}\n").unwrap();
+        assert_eq!(pieces[4], (TokenClass::Id, "data1"));
+
+        let ansi = highlight_ansi("value: 1 + x\n").unwrap();
+        assert_eq!(ansi, "\x1b[36mvalue\x1b[0m: \x1b[33m1\x1b[0m \x1b[31m+\x1b[0m x\n");
+    }
+
+    #[test]
+    fn highlight_broken_expressions() {
+        let options = PrintOptions { max_width: Some(30), ..PrintOptions::default() };
+        let cdl = "widget kpi {\n    value: IIF(alpha, beta, gamma + delta)\n    label: \"L\"\n}\n".to_string();
+        let printed = ::format(cdl, &options).unwrap();
+        assert!(printed.contains("\n        beta,\n"));
+
+        let pieces = highlight(&printed).unwrap();
+        let class_of = |text: &str| pieces.iter().find(|&&(_, t)| t == text).unwrap().0;
+        assert_eq!(class_of("widget"), TokenClass::EntityType);
+        assert_eq!(class_of("kpi"), TokenClass::SubType);
+        assert_eq!(class_of("IIF"), TokenClass::Function);
+        assert_eq!(class_of("alpha"), TokenClass::Identifier);
+        assert_eq!(class_of("beta"), TokenClass::Identifier);
+        assert_eq!(class_of("gamma"), TokenClass::Identifier);
+        assert_eq!(class_of("label"), TokenClass::Field);
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt;
+use lex::Lexer;
+use parse::{ParseResult, Parser};
+use serde_json::{self, Map, Value};
+use serde_yaml_ng;
+use tree::{self, TreeEntity, TreeExpr, TreeField};
+
+// Imports dashboards described as nested JSON or YAML, in the nested form of `tree::TreeEntity`.
+//
+// The document is either a single entity or an array of entities, where an entity looks like
+//
+//     {
+//         "mainType": "widget",
+//         "subType": "kpi",
+//         "identifier": "kpi1",
+//         "reference": "default",
+//         "fields": [
+//             { "identifier": "label", "value": "\"KPI\"" },
+//             { "identifier": "value", "value": "average(score(survey:Q1))" },
+//             { "identifier": "target", "value": { "type": "number", "value": 9, "text": "9" } }
+//         ],
+//         "children": [ ... ]
+//     }
+//
+// Only `mainType` is required. A field value is either an expression object as written by the `serde`
+// feature, or a string parsed as a CDL expression, so a CDL string needs its own quotes. Numbers and
+// booleans are written out and parsed the same way.
+
+#[derive(Debug, PartialEq)]
+pub struct ImportError {
+    /// JSON pointer to the value that could not be imported
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.pointer.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.pointer, self.message)
+        }
+    }
+}
+
+pub fn import_json(json: &str) -> Result<ParseResult, ImportError> {
+    let value: Value = serde_json::from_str(json).map_err(|e| error("", format!("Invalid JSON: {}", e)))?;
+    import_value(&value)
+}
+
+pub fn import_yaml(yaml: &str) -> Result<ParseResult, ImportError> {
+    let value: Value = serde_yaml_ng::from_str(yaml).map_err(|e| error("", format!("Invalid YAML: {}", e)))?;
+    import_value(&value)
+}
+
+pub fn import_value(value: &Value) -> Result<ParseResult, ImportError> {
+    Ok(tree::from_tree(&import_tree(value)?))
+}
+
+/// Reads the nested form of a document, turning expression strings into expression trees.
+pub fn import_tree(value: &Value) -> Result<Vec<TreeEntity>, ImportError> {
+    match value {
+        Value::Array(entities) => {
+            let mut tree = Vec::new();
+            for (index, entity) in entities.iter().enumerate() {
+                tree.push(import_entity(entity, &format!("/{}", index))?);
+            }
+            Ok(tree)
+        }
+        Value::Object(_) => Ok(vec![import_entity(value, "")?]),
+        _ => Err(error("", "Expected an entity or an array of entities".to_string())),
+    }
+}
+
+fn import_entity(value: &Value, pointer: &str) -> Result<TreeEntity, ImportError> {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return Err(error(pointer, "Expected an entity object".to_string())),
+    };
+    for key in object.keys() {
+        match key.as_str() {
+            "mainType" | "subType" | "identifier" | "reference" | "fields" | "children" => {}
+            _ => return Err(error(&child_pointer(pointer, key), format!("Unknown entity key '{}'", key))),
+        }
+    }
+
+    let main_type = match get_string(object, "mainType", pointer)? {
+        Some(main_type) => main_type,
+        None => return Err(error(pointer, "Entity is missing 'mainType'".to_string())),
+    };
+    let mut entity = TreeEntity {
+        main_type,
+        sub_type: get_string(object, "subType", pointer)?,
+        identifier: get_string(object, "identifier", pointer)?,
+        reference: get_string(object, "reference", pointer)?,
+        fields: Vec::new(),
+        children: Vec::new(),
+    };
+
+    match object.get("fields") {
+        Some(Value::Array(fields)) => {
+            let fields_pointer = child_pointer(pointer, "fields");
+            for (index, field) in fields.iter().enumerate() {
+                entity.fields.push(import_field(field, &format!("{}/{}", fields_pointer, index))?);
+            }
+        }
+        Some(_) => return Err(error(&child_pointer(pointer, "fields"), "Expected an array of fields".to_string())),
+        None => {}
+    }
+
+    match object.get("children") {
+        Some(Value::Array(children)) => {
+            let children_pointer = child_pointer(pointer, "children");
+            for (index, child) in children.iter().enumerate() {
+                entity.children.push(import_entity(child, &format!("{}/{}", children_pointer, index))?);
+            }
+        }
+        Some(_) => {
+            return Err(error(&child_pointer(pointer, "children"), "Expected an array of entities".to_string()))
+        }
+        None => {}
+    }
+
+    Ok(entity)
+}
+
+fn import_field(value: &Value, pointer: &str) -> Result<TreeField, ImportError> {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return Err(error(pointer, "Expected a field object".to_string())),
+    };
+    for key in object.keys() {
+        match key.as_str() {
+            "identifier" | "value" => {}
+            _ => return Err(error(&child_pointer(pointer, key), format!("Unknown field key '{}'", key))),
+        }
+    }
+
+    let identifier = match get_string(object, "identifier", pointer)? {
+        Some(identifier) => identifier,
+        None => return Err(error(pointer, "Field is missing 'identifier'".to_string())),
+    };
+    let value = match object.get("value") {
+        Some(value) => import_expr(value, &child_pointer(pointer, "value"))?,
+        None => return Err(error(pointer, "Field is missing 'value'".to_string())),
+    };
+    Ok(TreeField { identifier, value })
+}
+
+fn import_expr(value: &Value, pointer: &str) -> Result<TreeExpr, ImportError> {
+    let text = match value {
+        Value::String(s) => s.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Object(_) => {
+            let expr = serde_json::from_value(value.clone());
+            return expr.map_err(|e| error(pointer, format!("Invalid expression: {}", e)));
+        }
+        _ => return Err(error(pointer, "Expected an expression string, number, boolean or object".to_string())),
+    };
+    let lex_items = Lexer::new(text).lex().map_err(|e| error(pointer, e))?;
+    let parser = Parser::new(lex_items);
+    let mut pr = ParseResult::new();
+    let expr = parser.parse_expression(&mut pr).map_err(|e| error(pointer, e))?;
+    Ok(tree::expr_to_tree(&pr, expr))
+}
+
+fn get_string(object: &Map<String, Value>, key: &str, pointer: &str) -> Result<Option<String>, ImportError> {
+    match object.get(key) {
+        Some(Value::String(s)) => Ok(Some(s.to_string())),
+        Some(_) => Err(error(&child_pointer(pointer, key), format!("Expected '{}' to be a string", key))),
+        None => Ok(None),
+    }
+}
+
+fn child_pointer(pointer: &str, key: &str) -> String {
+    format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn error(pointer: &str, message: String) -> ImportError {
+    ImportError {
+        pointer: pointer.to_string(),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use import::{import_json, import_value, import_yaml};
+    use lex::Lexer;
+    use parse::Parser;
+    use print;
+    use serde_json;
+    use tree;
+
+    const CDL: &str = "page #overview {
+    widget kpi #kpi1 @default {
+        label: \"KPI\"
+        value: average(score(survey:Q7), @cr.currentPeriodB2b)
+        target: -9.5
+        shortForm: true
+    }
+}
+";
+
+    #[test]
+    fn import_json_document() {
+        let json = r#"[{
+            "mainType": "page",
+            "identifier": "overview",
+            "children": [{
+                "mainType": "widget",
+                "subType": "kpi",
+                "identifier": "kpi1",
+                "reference": "default",
+                "fields": [
+                    { "identifier": "label", "value": "\"KPI\"" },
+                    { "identifier": "value", "value": "average(score(survey:Q7), @cr.currentPeriodB2b)" },
+                    { "identifier": "target", "value": -9.5 },
+                    { "identifier": "shortForm", "value": true }
+                ]
+            }]
+        }]"#;
+        assert_eq!(print::print(import_json(json).unwrap()), CDL);
+    }
+
+    #[test]
+    fn import_yaml_document() {
+        let yaml = "
+mainType: page
+identifier: overview
+children:
+  - mainType: widget
+    subType: kpi
+    identifier: kpi1
+    reference: default
+    fields:
+      - identifier: label
+        value: '\"KPI\"'
+      - identifier: value
+        value: average(score(survey:Q7), @cr.currentPeriodB2b)
+      - identifier: target
+        value: -9.5
+      - identifier: shortForm
+        value: true
+";
+        assert_eq!(print::print(import_yaml(yaml).unwrap()), CDL);
+    }
+
+    #[test]
+    fn import_repeated_fields_and_expression_objects() {
+        let cdl = "widget markdown {
+    expr1: a
+    expr1: b + 1
+}
+";
+        let pr = import_value(&json!({
+            "mainType": "widget",
+            "subType": "markdown",
+            "fields": [
+                { "identifier": "expr1", "value": { "type": "identifier", "value": "a" } },
+                { "identifier": "expr1", "value": "b + 1" }
+            ]
+        })).unwrap();
+        assert_eq!(print::print(pr), cdl);
+    }
+
+    #[test]
+    fn import_the_nested_json_of_a_document() {
+        let parsed = Parser::new(Lexer::new(CDL.to_string()).lex().unwrap()).parse().unwrap();
+        let json = serde_json::to_string(&tree::to_tree(&parsed)).unwrap();
+        assert_eq!(format!("{:?}", import_json(&json).unwrap()), format!("{:?}", parsed));
+    }
+
+    #[test]
+    fn import_errors_have_pointers() {
+        let err = import_value(&json!([{ "mainType": "page", "children": [{ "subType": "kpi" }] }])).unwrap_err();
+        assert_eq!(err.pointer, "/0/children/0");
+        assert_eq!(err.to_string(), "/0/children/0: Entity is missing 'mainType'");
+
+        let err = import_value(&json!({ "mainType": "widget", "fields": [{ "value": "1" }] })).unwrap_err();
+        assert_eq!(err.to_string(), "/fields/0: Field is missing 'identifier'");
+
+        let fields = json!([{ "identifier": "a", "value": "1" }, { "identifier": "value", "value": "NPS(q1) q2" }]);
+        let err = import_value(&json!({ "mainType": "widget", "fields": fields })).unwrap_err();
+        assert_eq!(err.pointer, "/fields/1/value");
+
+        let value = json!({ "type": "operator", "operator": "+" });
+        let err = import_value(&json!({ "mainType": "widget", "fields": [{ "identifier": "a", "value": value }] }));
+        assert_eq!(err.unwrap_err().pointer, "/fields/0/value");
+
+        let err = import_value(&json!({ "mainType": "widget", "fields": { "a": "1" } })).unwrap_err();
+        assert_eq!(err.to_string(), "/fields: Expected an array of fields");
+
+        let err = import_value(&json!({ "mainType": "widget", "identifier": 1 })).unwrap_err();
+        assert_eq!(err.pointer, "/identifier");
+
+        let err = import_value(&json!({ "mainType": "widget", "size": "large" })).unwrap_err();
+        assert_eq!(err.pointer, "/size");
+
+        let err = import_json(r#"{ "mainType": "widget", "fields": [{ "identifier": "v", "value": "f(a" }] }"#);
+        assert_eq!(err.unwrap_err().pointer, "/fields/0/value");
+
+        assert_eq!(import_json("[").unwrap_err().pointer, "");
+    }
+}
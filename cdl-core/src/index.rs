@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+use parse::{EntityFieldRef, EntityRef, ParseResult};
+
+/// Lookups over the entities reachable from the root, built by `ParseResult::index` the first time they
+/// are needed. It has the parent of each entity and its place among its siblings. Entities are numbered
+/// in pre and post order, so `a` is an ancestor of `b` when it comes before `b` in pre order and after
+/// it in post order.
+///
+/// Every list is in document order. Detached entities and fields are not in the index.
+#[derive(Debug)]
+pub struct DocumentIndex {
+    order: Vec<EntityRef>,
+    parents: Vec<Option<EntityRef>>,
+    positions: Vec<usize>,
+    pre: Vec<usize>,
+    post: Vec<usize>,
+    depth: Vec<usize>,
+    field_entities: Vec<Option<EntityRef>>,
+    by_main_type: HashMap<String, Vec<EntityRef>>,
+    by_sub_type: HashMap<String, Vec<EntityRef>>,
+    by_identifier: HashMap<String, Vec<EntityRef>>,
+    by_field: HashMap<String, Vec<EntityFieldRef>>,
+}
+
+impl DocumentIndex {
+    pub fn new(pr: &ParseResult) -> DocumentIndex {
+        let mut index = DocumentIndex {
+            order: Vec::with_capacity(pr.entities.len()),
+            parents: vec![None; pr.entities.len()],
+            positions: vec![0; pr.entities.len()],
+            pre: vec![0; pr.entities.len()],
+            post: vec![0; pr.entities.len()],
+            depth: vec![0; pr.entities.len()],
+            field_entities: vec![None; pr.fields.len()],
+            by_main_type: HashMap::new(),
+            by_sub_type: HashMap::new(),
+            by_identifier: HashMap::new(),
+            by_field: HashMap::new(),
+        };
+
+        // an explicit stack, so deeply nested documents do not overflow. The flag tells if the
+        // children of the entity are done.
+        let mut finished = 0;
+        for (position, entity) in pr.root.children.iter().enumerate() {
+            index.positions[*entity] = position;
+        }
+        let mut stack: Vec<(EntityRef, bool)> = pr.root.children.iter().rev().map(|e| (*e, false)).collect();
+        while let Some((entity, done)) = stack.pop() {
+            if done {
+                index.post[entity] = finished;
+                finished += 1;
+                continue;
+            }
+            index.add(entity, pr);
+            stack.push((entity, true));
+            for (position, child) in pr.get_entity(entity).children.iter().enumerate().rev() {
+                index.parents[*child] = Some(entity);
+                index.positions[*child] = position;
+                index.depth[*child] = index.depth[entity] + 1;
+                stack.push((*child, false));
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, entity: EntityRef, pr: &ParseResult) {
+        let node = pr.get_entity(entity);
+        self.pre[entity] = self.order.len();
+        self.order.push(entity);
+        self.by_main_type.entry(node.main_type.clone()).or_default().push(entity);
+        if let Some(ref sub_type) = node.sub_type {
+            self.by_sub_type.entry(sub_type.clone()).or_default().push(entity);
+        }
+        if let Some(ref identifier) = node.identifier {
+            self.by_identifier.entry(identifier.clone()).or_default().push(entity);
+        }
+        for field in &node.fields {
+            self.field_entities[*field] = Some(entity);
+            self.by_field.entry(pr.get_field(*field).identifier.clone()).or_default().push(*field);
+        }
+    }
+
+    /// All attached entities, each before its children
+    pub fn document_order(&self) -> &[EntityRef] {
+        &self.order
+    }
+
+    /// The parent of an attached entity, `None` for root children
+    pub fn parent(&self, entity: EntityRef) -> Option<EntityRef> {
+        self.parents[entity]
+    }
+
+    /// The place of an attached entity among the children of its parent, or among the root children
+    pub fn sibling_position(&self, entity: EntityRef) -> usize {
+        self.positions[entity]
+    }
+
+    /// The position of an attached entity in document order
+    pub fn pre_order(&self, entity: EntityRef) -> usize {
+        self.pre[entity]
+    }
+
+    /// The position of an attached entity when every entity comes after its children
+    pub fn post_order(&self, entity: EntityRef) -> usize {
+        self.post[entity]
+    }
+
+    pub fn is_ancestor(&self, ancestor: EntityRef, entity: EntityRef) -> bool {
+        self.pre[ancestor] < self.pre[entity] && self.post[entity] < self.post[ancestor]
+    }
+
+    /// Everything below an attached entity, in document order
+    pub fn descendants(&self, entity: EntityRef) -> &[EntityRef] {
+        // the entities finished before `entity` are the ones before it that are not its ancestors, and
+        // the ones below it
+        let count = self.post[entity] + self.depth[entity] - self.pre[entity];
+        let start = self.pre[entity] + 1;
+        &self.order[start..start + count]
+    }
+
+    /// The entities `entity` is in, outermost first
+    pub fn path(&self, entity: EntityRef) -> Vec<EntityRef> {
+        let mut path = Vec::with_capacity(self.depth[entity]);
+        let mut current = self.parents[entity];
+        while let Some(parent) = current {
+            path.push(parent);
+            current = self.parents[parent];
+        }
+        path.reverse();
+        path
+    }
+
+    pub fn entities_with_type(&self, main_type: &str) -> &[EntityRef] {
+        self.by_main_type.get(main_type).map_or(&[], |e| e)
+    }
+
+    pub fn entities_with_sub_type(&self, sub_type: &str) -> &[EntityRef] {
+        self.by_sub_type.get(sub_type).map_or(&[], |e| e)
+    }
+
+    pub fn entities_with_id(&self, identifier: &str) -> &[EntityRef] {
+        self.by_identifier.get(identifier).map_or(&[], |e| e)
+    }
+
+    pub fn fields_named(&self, identifier: &str) -> &[EntityFieldRef] {
+        self.by_field.get(identifier).map_or(&[], |f| f)
+    }
+
+    /// The entity an attached field belongs to
+    pub fn field_entity(&self, field: EntityFieldRef) -> Option<EntityRef> {
+        self.field_entities[field]
+    }
+}
+
+/// Holds the index of a `ParseResult` once it is built. It is left out of the debug output, which
+/// would otherwise depend on whether anything has been selected.
+#[derive(Default)]
+pub struct IndexCell {
+    cell: OnceLock<DocumentIndex>,
+}
+
+impl IndexCell {
+    pub fn get_or_build(&self, pr: &ParseResult) -> &DocumentIndex {
+        self.cell.get_or_init(|| DocumentIndex::new(pr))
+    }
+
+    pub fn clear(&mut self) {
+        self.cell = OnceLock::new();
+    }
+}
+
+impl fmt::Debug for IndexCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("IndexCell")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use lex::Lexer;
+    use parse::{ParseResult, Parser};
+    use select::select_entity;
+
+    fn parse(cdl: &str) -> ParseResult {
+        let lexer = Lexer::new(cdl.to_string());
+        let parser = Parser::new(lexer.lex().unwrap());
+        parser.parse().unwrap()
+    }
+
+    fn main_types(pr: &ParseResult, entities: &[usize]) -> Vec<String> {
+        entities.iter().map(|e| pr.get_entity(*e).main_type.clone()).collect()
+    }
+
+    const SCRIPT: &str = "
+page #overview {
+    widget kpi #kpi1 {
+        label: \"KPI\"
+        tile value {
+            label: \"Value\"
+        }
+    }
+    widget table {
+        column text #c1 {
+            label: \"Name\"
+        }
+    }
+}
+page #details {
+}
+";
+
+    #[test]
+    fn index_order_and_ancestors() {
+        let pr = parse(SCRIPT);
+        let index = pr.index();
+        assert_eq!(main_types(&pr, index.document_order()), vec!["page", "widget", "tile", "widget", "column", "page"]);
+
+        let order = index.document_order().to_vec();
+        let (page, kpi, tile, table, column, details) = (order[0], order[1], order[2], order[3], order[4], order[5]);
+        assert_eq!(index.parent(tile), Some(kpi));
+        assert_eq!(index.parent(page), None);
+        assert_eq!(index.path(column), vec![page, table]);
+        assert_eq!(index.sibling_position(table), 1);
+        assert_eq!(index.sibling_position(details), 1);
+        assert_eq!(index.sibling_position(column), 0);
+        assert_eq!(index.post_order(tile), 0);
+        assert_eq!(index.post_order(details), 5);
+
+        assert!(index.is_ancestor(page, tile));
+        assert!(index.is_ancestor(kpi, tile));
+        assert!(!index.is_ancestor(table, tile));
+        assert!(!index.is_ancestor(tile, tile));
+        assert!(!index.is_ancestor(page, details));
+
+        assert_eq!(index.descendants(page), &[kpi, tile, table, column][..]);
+        assert_eq!(index.descendants(table), &[column][..]);
+        assert!(index.descendants(details).is_empty());
+    }
+
+    #[test]
+    fn index_lookups() {
+        let pr = parse(SCRIPT);
+        let index = pr.index();
+        assert_eq!(index.entities_with_type("widget").len(), 2);
+        assert_eq!(main_types(&pr, index.entities_with_sub_type("kpi")), vec!["widget"]);
+        assert_eq!(main_types(&pr, index.entities_with_id("c1")), vec!["column"]);
+        assert!(index.entities_with_type("filter").is_empty());
+
+        let labels = index.fields_named("label");
+        assert_eq!(labels.len(), 3);
+        let owners: Vec<String> = labels.iter().map(|f| pr.get_entity(index.field_entity(*f).unwrap()).main_type.clone())
+            .collect();
+        assert_eq!(owners, vec!["widget", "tile", "column"]);
+    }
+
+    #[test]
+    fn index_is_rebuilt_after_edits() {
+        let mut pr = parse(SCRIPT);
+        let column = pr.index().entities_with_id("c1")[0];
+        assert_eq!(pr.index().document_order().len(), 6);
+
+        pr.remove_entity(column).unwrap();
+        assert_eq!(pr.index().document_order().len(), 5);
+        assert!(pr.index().entities_with_id("c1").is_empty());
+        assert!(pr.index().fields_named("label").iter().all(|f| pr.index().field_entity(*f).is_some()));
+        assert_eq!(pr.index().fields_named("label").len(), 2);
+    }
+
+    #[test]
+    fn index_is_rebuilt_after_invalidating() {
+        let mut pr = parse(SCRIPT);
+        let details = pr.root.children[1];
+        assert_eq!(select_entity(&pr, "page:first-child").len(), 1);
+
+        pr.root.children.swap(0, 1);
+        pr.entities[details].main_type = "dashboard".to_string();
+        pr.invalidate_index();
+        assert_eq!(pr.index().sibling_position(details), 0);
+        assert_eq!(select_entity(&pr, "dashboard:first-child").len(), 1);
+        assert_eq!(select_entity(&pr, "page:first-child").len(), 0);
+    }
+
+    #[test]
+    fn parse_results_are_sync() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<ParseResult>();
+    }
+}
@@ -1,8 +1,12 @@
+use std::cell::Cell;
 use std::iter::Peekable;
+use std::str::Chars;
 
 #[derive(Debug, PartialEq)]
 pub enum LexItem {
     Identifier(String),
+    /// `#name`, an entity id in a header or a color in a value
+    Id(String),
     String(String),
     Reference(String),
     Number { value: f64, real_text: String },
@@ -23,10 +27,41 @@ pub enum LexItem {
     EOL,
 }
 
+/// Byte offsets of a token in the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 pub struct Lexer {
     input: String
 }
 
+// Counts the bytes taken from the input, so tokens can be given their spans.
+pub(crate) struct CountingChars<'a> {
+    chars: Chars<'a>,
+    pos: &'a Cell<usize>,
+}
+
+impl<'a> CountingChars<'a> {
+    pub(crate) fn new(input: &'a str, pos: &'a Cell<usize>) -> CountingChars<'a> {
+        CountingChars { chars: input.chars(), pos }
+    }
+}
+
+impl<'a> Iterator for CountingChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if let Some(c) = c {
+            self.pos.set(self.pos.get() + c.len_utf8());
+        }
+        c
+    }
+}
+
 impl Lexer {
     pub fn new(input: String) -> Lexer {
         Lexer {
@@ -35,9 +70,20 @@ impl Lexer {
     }
 
     pub fn lex(&self) -> Result<Vec<LexItem>, String> {
+        Ok(self.lex_with_spans()?.into_iter().map(|(item, _)| item).collect())
+    }
+
+    /// Lexes the input, keeping where each token was found. Everything between the spans is whitespace,
+    /// comments or characters the lexer skipped.
+    pub fn lex_with_spans(&self) -> Result<Vec<(LexItem, Span)>, String> {
         let mut result = Vec::new();
-        let mut it = self.input.chars().peekable();
+        let mut spans = Vec::new();
+        let pos = Cell::new(0);
+        let mut it = CountingChars::new(&self.input, &pos).peekable();
         while let Some(&c) = it.peek() {
+            // the peeked char has already been counted
+            let start = pos.get() - c.len_utf8();
+            let token_count = result.len();
             match c {
                 'a'...'z' | 'A'...'Z' | '_' => {
                     it.next();
@@ -57,7 +103,7 @@ impl Lexer {
                 '#' => {
                     it.next();
                     let id = get_id(&mut it);
-                    result.push(LexItem::Identifier(id));
+                    result.push(LexItem::Id(id));
                 }
 
                 '{' => {
@@ -76,7 +122,7 @@ impl Lexer {
                     result.push(LexItem::EOL);
                     it.next();
                 }
-                ' ' => {
+                ' ' | '\t' | '\r' => {
                     it.next();
                 }
                 '"' => {
@@ -111,8 +157,12 @@ impl Lexer {
                     it.next();
                 }
                 '/' => {
-                    result.push(LexItem::Div);
                     it.next();
+                    if it.peek() == Some(&'/') {
+                        skip_comment(&mut it);
+                    } else {
+                        result.push(LexItem::Div);
+                    }
                 }
                 '*' => {
                     result.push(LexItem::Mul);
@@ -139,8 +189,24 @@ impl Lexer {
                     it.next();
                 }
             }
+            if result.len() > token_count {
+                // peek before reading the position, peeking may count the next char
+                let peeked = it.peek().map_or(0, |c| c.len_utf8());
+                let end = pos.get() - peeked;
+                spans.push(Span { start, end });
+            }
         }
-        Ok(result)
+        Ok(result.into_iter().zip(spans).collect())
+    }
+}
+
+// skips a `//` comment, up to but not including the end of the line
+fn skip_comment<T: Iterator<Item=char>>(iter: &mut Peekable<T>) {
+    while let Some(&ch) = iter.peek() {
+        if ch == '\n' {
+            break;
+        }
+        iter.next();
     }
 }
 
@@ -243,7 +309,7 @@ fn get_single_quoted_string<T: Iterator<Item=char>>(iter: &mut Peekable<T>) -> S
 
 #[cfg(test)]
 mod test {
-    use lex::Lexer;
+    use lex::{LexItem, Lexer};
 
     #[test]
     fn simple_lex() {
@@ -289,6 +355,29 @@ mod test {
         assert_eq!(lex_items.len(), 15);
     }
 
+    #[test]
+    fn lex_comment() {
+        let cdl = "// accounts --> Health
+widget kpi { // trailing
+    value: a / b
+}".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        assert_eq!(lex_items.len(), 12);
+        assert_eq!(lex_items[0], LexItem::EOL);
+        assert_eq!(lex_items[8], LexItem::Div);
+    }
+
+    #[test]
+    fn lex_spans() {
+        let cdl = "widget #kpi {\n  label: 'Ø'\n}";
+        let lexer = Lexer::new(cdl.to_string());
+        let texts: Vec<&str> = lexer.lex_with_spans().unwrap().iter()
+            .map(|&(_, span)| &cdl[span.start..span.end])
+            .collect();
+        assert_eq!(texts, vec!["widget", "#kpi", "{", "\n", "label", ":", "'Ø'", "\n", "}"]);
+    }
+
     #[test]
     fn lex_advanced_expr() {
         let cdl = "value: MAX(survey:Q2,survey:interview_start=max(survey:interview_start))
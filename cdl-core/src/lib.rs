@@ -1,12 +1,43 @@
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(any(feature = "import", all(test, feature = "serde")))]
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
+#[cfg(feature = "import")]
+extern crate serde_yaml_ng;
+extern crate regex;
+
 mod lex;
 mod parse;
 mod print;
 mod select;
+mod edit;
+mod index;
+mod structural;
+mod cst;
+mod pretty;
+mod highlight;
+mod docgen;
+pub mod build;
+pub mod tree;
+#[cfg(feature = "import")]
+pub mod import;
 
-use parse::ParseResult;
 use parse::Parser;
 pub use lex::Lexer;
-pub use select::{select_field, select_entity};
+pub use cst::Cst;
+pub use index::DocumentIndex;
+pub use docgen::{dashboard_markdown, dashboard_html};
+pub use highlight::{TokenClass, comments, highlight, highlight_html, highlight_ansi};
+pub use parse::{ParseResult, AstRootNode, AstEntityNode, AstFieldNode, Expr, AstStringNode, AstIdentifierNode,
+                AstNumberNode, AstFunctionNode, AstOperatorNode, AstUnaryOperatorNode, AstVPathNode, AstReferenceNode,
+                EntityRef, EntityFieldRef, EntityExprRef, NodeRef};
+pub use structural::{StructuralKey, StructuralOptions, nodes_equal, entities_equal, fields_equal, exprs_equal,
+                     structural_hash};
+pub use select::{select_field, select_entity, select_expr, select_entity_ids, select_field_ids, EntityMatch, FieldMatch,
+                 ExprMatch, Selector, CompiledSelector, SelectorError};
+pub use print::{PrintOptions, Indent, QuoteStyle, HierarchyOptions, print_node, write_node, write_document,
+                print_hierarchy, write_hierarchy};
 
 pub fn compile(cdl: String) -> Result<ParseResult, String> {
     let lexer = Lexer::new(cdl);
@@ -19,3 +50,21 @@ pub fn compile(cdl: String) -> Result<ParseResult, String> {
 pub fn print(root: ParseResult) -> String {
     print::print(root)
 }
+
+pub fn print_with_options(root: &ParseResult, options: &PrintOptions) -> String {
+    print::print_with_options(root, options)
+}
+
+/// Parses a script and prints it back with everything the parser does not need left out.
+pub fn minify(cdl: String) -> Result<String, String> {
+    format(cdl, &PrintOptions::minified())
+}
+
+/// Parses a script and prints it back in the style given by `options`.
+pub fn format(cdl: String, options: &PrintOptions) -> Result<String, String> {
+    let lexer = Lexer::new(cdl);
+    let lex_items = lexer.lex()?;
+    let parser = Parser::new(lex_items);
+    let root = parser.parse()?;
+    Ok(print::print_with_options(&root, options))
+}
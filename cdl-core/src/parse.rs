@@ -1,7 +1,11 @@
 use std::cell::{Cell, Ref, RefCell};
 use lex::LexItem;
+use index::{DocumentIndex, IndexCell};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Expr {
     String(Box<AstStringNode>),
     Identifier(Box<AstIdentifierNode>),
@@ -10,19 +14,29 @@ pub enum Expr {
     VPath(Box<AstVPathNode>),
     Operator(Box<AstOperatorNode>),
     UnaryOperator(Box<AstUnaryOperatorNode>),
+    Reference(Box<AstReferenceNode>),
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstStringNode {
     pub value: String
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstIdentifierNode {
     pub value: String
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AstReferenceNode {
+    pub value: String
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstNumberNode {
     pub value: f64,
     pub text_rep: String,
@@ -38,12 +52,14 @@ impl AstNumberNode {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstFunctionNode {
     pub identifier: String,
     pub argument_list: Vec<EntityExprRef>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstOperatorNode {
     pub operator: char,
     pub left_side: EntityExprRef,
@@ -51,6 +67,7 @@ pub struct AstOperatorNode {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstUnaryOperatorNode {
     pub operator: char,
     pub expr: EntityExprRef,
@@ -58,6 +75,7 @@ pub struct AstUnaryOperatorNode {
 
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstVPathNode {
     pub table: Option<String>,
     pub sub_table: Option<String>,
@@ -66,11 +84,13 @@ pub struct AstVPathNode {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstRootNode {
     pub children: Vec<EntityRef>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstEntityNode {
     pub main_type: String,
     pub sub_type: Option<String>,
@@ -95,31 +115,67 @@ impl AstEntityNode {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AstFieldNode {
     pub identifier: String,
     pub value: EntityExprRef,
 }
 
 
-type EntityRef = usize;
-type EntityFieldRef = usize;
-type EntityExprRef = usize;
+pub type EntityRef = usize;
+pub type EntityFieldRef = usize;
+pub type EntityExprRef = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeRef {
+    Entity(EntityRef),
+    Field(EntityFieldRef),
+    Expr(EntityExprRef),
+}
 
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ParseResult {
     pub root: AstRootNode,
     pub entities: Vec<AstEntityNode>,
     pub fields: Vec<AstFieldNode>,
     pub expressions: Vec<Expr>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    index: IndexCell,
 }
 
 impl ParseResult {
+    pub fn new() -> ParseResult {
+        ParseResult {
+            root: AstRootNode {
+                children: Vec::new(),
+            },
+            entities: Vec::new(),
+            fields: Vec::new(),
+            expressions: Vec::new(),
+            index: IndexCell::default(),
+        }
+    }
+
+    /// Lookups by type, id and field name and the parent of each entity. Built on the first call, and
+    /// again after the tree is changed through the methods of `ParseResult`. Code that changes the
+    /// public fields directly must call `invalidate_index` afterwards, until then the index describes
+    /// the document as it was.
+    pub fn index(&self) -> &DocumentIndex {
+        self.index.get_or_build(self)
+    }
+
+    pub fn invalidate_index(&mut self) {
+        self.index.clear();
+    }
+
     pub fn get_entity(&self, r: EntityRef) -> &AstEntityNode {
         &self.entities[r]
     }
 
     pub fn add_entity(&mut self, node: AstEntityNode) -> EntityRef {
+        self.invalidate_index();
         self.entities.push(node);
         self.entities.len() - 1
     }
@@ -129,6 +185,7 @@ impl ParseResult {
     }
 
     pub fn add_field(&mut self, node: AstFieldNode) -> EntityFieldRef {
+        self.invalidate_index();
         self.fields.push(node);
         self.fields.len() - 1
     }
@@ -143,11 +200,27 @@ impl ParseResult {
     }
 }
 
+impl Default for ParseResult {
+    fn default() -> ParseResult {
+        ParseResult::new()
+    }
+}
+
+
+/// Where the parsed nodes were found in the token stream, as token indexes
+#[derive(Debug, Default)]
+pub struct TokenRanges {
+    /// entity, its first token, the `{` token and one past the `}` token
+    pub entities: Vec<(EntityRef, usize, usize, usize)>,
+    /// field, its first token, the first token of the value and one past the last token of the value
+    pub fields: Vec<(EntityFieldRef, usize, usize, usize)>,
+}
 
 #[derive(Debug)]
 pub struct Parser {
     tokens: RefCell<Vec<LexItem>>,
     index: Cell<usize>,
+    ranges: RefCell<TokenRanges>,
 }
 
 
@@ -156,6 +229,7 @@ impl Parser {
         Parser {
             tokens: RefCell::new(tokens),
             index: Cell::new(0),
+            ranges: RefCell::new(TokenRanges::default()),
         }
     }
 
@@ -187,27 +261,26 @@ impl Parser {
         self.index.get() < self.tokens.borrow().len()
     }
 
-    fn eat_token_if(&self, token: LexItem) {
+    fn eat_token_if(&self, token: LexItem) -> Result<(), String> {
         if *self.peek_current_token() == token {
             self.advance_stream();
+            Ok(())
         } else {
-            panic!("Trying to advance the token stream, but got unexpected token.\n\
-                    Got {:?} expexted {:?} ", self.peek_current_token(), token);
+            Err(format!("Trying to advance the token stream, but got unexpected token.\n\
+                    Got {:?} expexted {:?} ", self.peek_current_token(), token))
         }
     }
 
+    pub fn parse_with_ranges(&self) -> Result<(ParseResult, TokenRanges), String> {
+        let pr = self.parse()?;
+        Ok((pr, self.ranges.replace(TokenRanges::default())))
+    }
+
     pub fn parse(&self) -> Result<ParseResult, String> {
         let mut root = AstRootNode {
             children: Vec::new(),
         };
-        let mut pr = ParseResult {
-            root: AstRootNode {
-                children: Vec::new(),
-            },
-            entities: Vec::new(),
-            fields: Vec::new(),
-            expressions: Vec::new(),
-        };
+        let mut pr = ParseResult::new();
         while self.has_items() {
             match *self.peek_current_token() {
                 LexItem::EOL => {
@@ -225,6 +298,7 @@ impl Parser {
     }
 
     fn parse_entity(&self, pr: &mut ParseResult) -> Result<EntityRef, String> {
+        let start = self.index.get();
         let mut node = AstEntityNode::new();
         match *self.get_current_token() {
             LexItem::Identifier(ref m) => node.main_type = m.to_string(),
@@ -254,8 +328,10 @@ impl Parser {
             }
             None => {}
         }
-        self.eat_token_if(LexItem::OpenBracket);
-        self.eat_token_if(LexItem::EOL);
+        let open = self.index.get();
+        self.eat_token_if(LexItem::OpenBracket)?;
+        self.eat_token_if(LexItem::EOL)?;
+        let end;
         let mut fields = Vec::new();
         let mut entities = Vec::new();
 
@@ -263,8 +339,9 @@ impl Parser {
             // are we done?
             match *self.peek_current_token() {
                 LexItem::CloseBracket => {
-                    self.eat_token_if(LexItem::CloseBracket);
-                    self.eat_token_if(LexItem::EOL);
+                    self.eat_token_if(LexItem::CloseBracket)?;
+                    end = self.index.get();
+                    self.eat_token_if(LexItem::EOL)?;
                     break;
                 }
                 _ => {}
@@ -272,7 +349,7 @@ impl Parser {
             // skip blank lines
             match *self.peek_current_token() {
                 LexItem::EOL => {
-                    self.eat_token_if(LexItem::EOL);
+                    self.eat_token_if(LexItem::EOL)?;
                     continue;
                 }
                 _ => {}
@@ -288,6 +365,7 @@ impl Parser {
         node.children = entities;
         node.fields = fields;
         let index = pr.add_entity(node);
+        self.ranges.borrow_mut().entities.push((index, start, open, end));
         Ok(index)
     }
 
@@ -305,14 +383,16 @@ impl Parser {
         }
     }
 
+    // `#id`, or a plain name after the sub type
     fn get_entity_id(&self) -> Option<String> {
         match *self.peek_current_token() {
-            LexItem::Identifier(ref s) => Some(s.to_string()),
+            LexItem::Id(ref s) | LexItem::Identifier(ref s) => Some(s.to_string()),
             _ => None
         }
     }
 
     fn parse_field(&self, pr: &mut ParseResult) -> Result<EntityFieldRef, String> {
+        let start = self.index.get();
         let mut node = AstFieldNode {
             identifier: String::new(),
             value: 0,
@@ -323,14 +403,33 @@ impl Parser {
             ref identifier @ _ => return Err(format!("Didnt find field identifier, instead got {:?}", identifier))
         }
 
-        self.eat_token_if(LexItem::Colon);
+        self.eat_token_if(LexItem::Colon)?;
+        let value_start = self.index.get();
         node.value = self.parse_expr(pr)?;
         let index = pr.add_field(node);
-        self.eat_token_if(LexItem::EOL);
+        self.ranges.borrow_mut().fields.push((index, start, value_start, self.index.get()));
+        self.eat_token_if(LexItem::EOL)?;
         Ok(index)
     }
 
 
+    /// Parses a single expression, used when expressions come from somewhere else than a CDL script.
+    pub fn parse_expression(&self, pr: &mut ParseResult) -> Result<EntityExprRef, String> {
+        self.tokens.borrow_mut().push(LexItem::EOL);
+        let expr = self.parse_expr(pr)?;
+        match *self.peek_current_token() {
+            LexItem::EOL if self.index.get() + 1 == self.tokens.borrow().len() => Ok(expr),
+            ref t => Err(format!("Found unexpected token after expression: {:?}", t)),
+        }
+    }
+
+    // a line break right after an operator or inside parentheses does not end the expression
+    fn skip_line_breaks(&self) {
+        while self.index.get() + 1 < self.tokens.borrow().len() && *self.peek_current_token() == LexItem::EOL {
+            self.advance_stream();
+        }
+    }
+
     // E --> T {( "+" | "-" ) T}
     fn parse_expr(&self, pr: &mut ParseResult) -> Result<EntityExprRef, String> {
         let mut current_expr = self.parse_term(pr)?;
@@ -338,6 +437,7 @@ impl Parser {
             match *self.peek_current_token() {
                 LexItem::Minus => {
                     self.advance_stream();
+                    self.skip_line_breaks();
                     let right_side = self.parse_term(pr)?;
                     let index = pr.add_expr(Expr::Operator(Box::new(AstOperatorNode {
                         operator: '-',
@@ -348,6 +448,7 @@ impl Parser {
                 }
                 LexItem::Plus => {
                     self.advance_stream();
+                    self.skip_line_breaks();
                     let right_side = self.parse_term(pr)?;
                     let index = pr.add_expr(Expr::Operator(Box::new(AstOperatorNode {
                         operator: '+',
@@ -375,6 +476,7 @@ impl Parser {
             match *self.peek_current_token() {
                 LexItem::Mul => {
                     self.advance_stream();
+                    self.skip_line_breaks();
                     let right_side = self.parse_factor(pr)?;
                     let index = pr.add_expr(Expr::Operator(Box::new(AstOperatorNode {
                         operator: '*',
@@ -385,6 +487,7 @@ impl Parser {
                 }
                 LexItem::Div => {
                     self.advance_stream();
+                    self.skip_line_breaks();
                     let right_side = self.parse_factor(pr)?;
                     let index = pr.add_expr(Expr::Operator(Box::new(AstOperatorNode {
                         operator: '/',
@@ -439,10 +542,27 @@ impl Parser {
                     }
                 }
             }
+            // a color, like `#e8f8e0`
+            LexItem::Id(ref s) => {
+                self.advance_stream();
+                let index = pr.add_expr(Expr::Identifier(Box::new(AstIdentifierNode {
+                    value: s.to_string(),
+                })));
+                return Ok(index);
+            }
+            LexItem::Reference(ref s) => {
+                self.advance_stream();
+                let index = pr.add_expr(Expr::Reference(Box::new(AstReferenceNode {
+                    value: s.to_string(),
+                })));
+                return Ok(index);
+            }
             LexItem::OpenPar => {
                 self.advance_stream();
+                self.skip_line_breaks();
                 let expr = self.parse_expr(pr)?;
-                self.eat_token_if(LexItem::ClosePar);
+                self.skip_line_breaks();
+                self.eat_token_if(LexItem::ClosePar)?;
                 return Ok(expr);
             }
             LexItem::Minus => {
@@ -465,7 +585,7 @@ impl Parser {
             }
             ref t @ _ => return Err(format!("Found unexpected token when trying to parse vpath: {:?}", t))
         };
-        self.eat_token_if(LexItem::Colon);
+        self.eat_token_if(LexItem::Colon)?;
         let question = match *self.get_current_token() {
             LexItem::Identifier(ref s) => {
                 s.to_string()
@@ -488,9 +608,9 @@ impl Parser {
             }
             ref t @ _ => return Err(format!("Found unexpected token when trying to parse function: {:?}", t))
         };
-        self.eat_token_if(LexItem::OpenPar);
+        self.eat_token_if(LexItem::OpenPar)?;
         let arg_list = self.parse_arg_list(pr)?;
-        self.eat_token_if(LexItem::ClosePar);
+        self.eat_token_if(LexItem::ClosePar)?;
         let index = pr.add_expr(Expr::Function(Box::new(AstFunctionNode {
             identifier: name,
             argument_list: arg_list,
@@ -500,17 +620,26 @@ impl Parser {
 
     fn parse_arg_list(&self, pr: &mut ParseResult) -> Result<Vec<EntityExprRef>, String> {
         let mut args = Vec::new();
+        // arguments may be split over lines after `(` and `,`, and the `)` may be on a line of its own
+        self.skip_line_breaks();
+        if self.has_items() && *self.peek_current_token() == LexItem::ClosePar {
+            return Ok(args);
+        }
         loop {
+            args.push(self.parse_expr(pr)?);
+            self.skip_line_breaks();
+            if !self.has_items() {
+                return Err("Expected ',' or ')' after function argument, found the end".to_string());
+            }
             match *self.peek_current_token() {
                 LexItem::Comma => {
                     self.advance_stream();
+                    self.skip_line_breaks();
                 }
                 LexItem::ClosePar => {
                     return Ok(args);
                 }
-                _ => {
-                    args.push(self.parse_expr(pr)?);
-                }
+                ref t => return Err(format!("Expected ',' or ')' after function argument, found {:?}", t)),
             }
         }
     }
@@ -519,7 +648,7 @@ impl Parser {
 #[cfg(test)]
 mod test {
     use lex::Lexer;
-    use parse::Parser;
+    use parse::{Expr, ParseResult, Parser};
 
     const EXPR_CDL: &str = "widget kpi   {
     expr1: 1 + 1
@@ -535,6 +664,49 @@ mod test {
 }
 ";
 
+    #[test]
+    fn parse_reference_in_expr() {
+        let cdl = "widget kpi {
+    value: average(score(survey:Q7), @cr.currentPeriodB2b)
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let pr = parser.parse().unwrap();
+        assert_eq!(pr.expressions.len(), 4);
+        match pr.expressions[2] {
+            Expr::Reference(ref node) => assert_eq!(node.value, "cr.currentPeriodB2b"),
+            ref e => panic!("Expected reference, found {:?}", e),
+        }
+    }
+
+    #[test]
+    fn parse_arguments_over_lines() {
+        let parse = |cdl: &str| {
+            let lexer = Lexer::new(cdl.to_string());
+            Parser::new(lexer.lex().unwrap()).parse()
+        };
+        let pr = parse("widget kpi {\n    value: IIF(\n        a,\n        MAX(b, c),\n        d\n    )\n}\n").unwrap();
+        match pr.expressions[pr.fields[0].value] {
+            Expr::Function(ref node) => assert_eq!(node.argument_list.len(), 3),
+            ref e => panic!("Expected function, found {:?}", e),
+        }
+
+        // a line break is not an argument separator
+        assert!(parse("widget kpi {\n    value: f(a\n b)\n}\n").is_err());
+        assert!(parse("widget kpi {\n    value: f(a b)\n}\n").is_err());
+        assert!(parse("widget kpi {\n    value: f(a\n}\n").is_err());
+    }
+
+    #[test]
+    fn parse_unclosed_argument_list() {
+        let lexer = Lexer::new("f(a".to_string());
+        let parser = Parser::new(lexer.lex().unwrap());
+        let mut pr = ParseResult::new();
+        assert!(parser.parse_expression(&mut pr).is_err());
+    }
+
     #[test]
     fn parse_entity() {
         let cdl = "widget kpi {
@@ -0,0 +1,137 @@
+// Document algebra after Wadler's "A prettier printer", used to lay out expressions that are too long
+// for one line. A group is printed flat when it fits in what is left of the line, otherwise its line
+// breaks become new lines indented to the group's nesting level.
+
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// a space, or a new line when the enclosing group is broken
+    Line,
+    /// nothing, or a new line when the enclosing group is broken
+    SoftLine,
+    /// one indent level deeper for the lines inside
+    Nest(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+pub fn text<S: Into<String>>(s: S) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Lays out `doc` starting at `column` on a line indented `level` times with `unit`.
+/// Without a `max_width` everything is printed flat.
+pub fn render(doc: &Doc, max_width: Option<usize>, column: usize, level: usize, unit: &str) -> String {
+    let mut out = String::new();
+    let mut column = column;
+    let mut stack = vec![(level, Mode::Break, doc)];
+    while let Some((level, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += width(s);
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if let Doc::Line = doc {
+                    out.push(' ');
+                    column += 1;
+                }
+            }
+            Doc::Line | Doc::SoftLine => {
+                let indent = unit.repeat(level);
+                out.push('\n');
+                out.push_str(&indent);
+                column = width(&indent);
+            }
+            Doc::Nest(doc) => stack.push((level + 1, mode, doc)),
+            Doc::Group(doc) => {
+                let flat = match max_width {
+                    Some(max_width) => mode == Mode::Flat || fits(max_width as isize - column as isize, doc, &stack),
+                    None => true,
+                };
+                stack.push((level, if flat { Mode::Flat } else { Mode::Break }, doc));
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((level, mode, doc));
+                }
+            }
+        }
+    }
+    out
+}
+
+// Tells if `doc` printed flat, and what follows it up to the next line break, fits in `remaining` columns.
+fn fits(remaining: isize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut remaining = remaining;
+    let mut docs = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    while remaining >= 0 {
+        let (mode, doc) = match docs.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(s) => remaining -= width(s) as isize,
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::Line => remaining -= 1,
+            Doc::SoftLine => {}
+            Doc::Nest(doc) => docs.push((mode, doc)),
+            Doc::Group(doc) => docs.push((Mode::Flat, doc)),
+            Doc::Concat(list) => {
+                for doc in list.iter().rev() {
+                    docs.push((mode, doc));
+                }
+            }
+        }
+    }
+    false
+}
+
+// tabs count as four columns
+pub fn width(s: &str) -> usize {
+    s.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use pretty::{group, nest, render, text, Doc};
+
+    fn call(name: &str, args: Vec<Doc>) -> Doc {
+        let mut inner = vec![Doc::SoftLine];
+        for (i, arg) in args.into_iter().enumerate() {
+            if i > 0 {
+                inner.push(text(","));
+                inner.push(Doc::Line);
+            }
+            inner.push(arg);
+        }
+        group(Doc::Concat(vec![text(format!("{}(", name)), nest(Doc::Concat(inner)), Doc::SoftLine, text(")")]))
+    }
+
+    #[test]
+    fn groups_break_only_when_needed() {
+        let doc = call("IIF", vec![text("a"), call("MAX", vec![text("b"), text("c")]), text("d")]);
+        assert_eq!(render(&doc, None, 0, 0, "  "), "IIF(a, MAX(b, c), d)");
+        assert_eq!(render(&doc, Some(20), 0, 0, "  "), "IIF(a, MAX(b, c), d)");
+        assert_eq!(render(&doc, Some(19), 0, 0, "  "), "IIF(\n  a,\n  MAX(b, c),\n  d\n)");
+        assert_eq!(render(&doc, Some(10), 4, 2, "  "), "IIF(\n      a,\n      MAX(\n        b,\n        c\n      ),\n      d\n    )");
+    }
+}
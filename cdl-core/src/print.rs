@@ -1,27 +1,346 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
 use parse::AstEntityNode;
 use parse::AstFieldNode;
+use parse::AstOperatorNode;
 use parse::Expr;
+use parse::NodeRef;
 use parse::ParseResult;
-use std;
+use pretty::{group, nest, render, text, width, Doc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteStyle {
+    Double,
+    Single,
+}
+
+/// How a document is laid out when printed. The default is the style `print` has always used.
+///
+/// Options can be read from a config file with one `key = value` per line and `#` comments:
+///
+/// ```text
+/// indent = 2                      # number of spaces, or `tab`
+/// space_before_colon = false
+/// space_after_colon = true
+/// blank_lines_between_entities = 1
+/// quote_style = double            # or `single`
+/// trailing_newline = true
+/// fields_first = true             # fields before child entities
+/// max_width = 100                 # or `none`, longer expressions are broken over lines
+/// canonical = false
+/// minify = false
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintOptions {
+    pub indent: Indent,
+    pub space_before_colon: bool,
+    pub space_after_colon: bool,
+    pub blank_lines_between_entities: usize,
+    pub quote_style: QuoteStyle,
+    pub trailing_newline: bool,
+    pub fields_first: bool,
+    pub max_width: Option<usize>,
+    /// sort fields by name, normalise number text and upper-case known function names, so scripts that
+    /// mean the same print the same. Fields are only sorted in entities where each field name is used
+    /// once, a repeated field can depend on its place among the others.
+    pub canonical: bool,
+    /// leave out every space the parser does not need, and write numbers in their shortest form
+    pub minify: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            indent: Indent::Spaces(4),
+            space_before_colon: false,
+            space_after_colon: true,
+            blank_lines_between_entities: 0,
+            quote_style: QuoteStyle::Double,
+            trailing_newline: true,
+            fields_first: true,
+            max_width: None,
+            canonical: false,
+            minify: false,
+        }
+    }
+}
+
+impl PrintOptions {
+    /// The one normalised form used for comparing scripts. Printing it again gives the same text.
+    pub fn canonical() -> PrintOptions {
+        PrintOptions {
+            canonical: true,
+            ..PrintOptions::default()
+        }
+    }
+
+    /// The smallest text the parser still reads back to the same document, for shipping scripts.
+    pub fn minified() -> PrintOptions {
+        PrintOptions {
+            indent: Indent::Spaces(0),
+            space_after_colon: false,
+            minify: true,
+            ..PrintOptions::default()
+        }
+    }
+
+    /// Reads options from config text, options that are not mentioned keep their default.
+    pub fn from_config(config: &str) -> Result<PrintOptions, String> {
+        let mut options = PrintOptions::default();
+        for (index, line) in config.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(eq) => (line[..eq].trim(), line[eq + 1..].trim()),
+                None => return Err(format!("Line {}: expected `key = value`, found '{}'", index + 1, line)),
+            };
+            options.set(key, value).map_err(|e| format!("Line {}: {}", index + 1, e))?;
+        }
+        Ok(options)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<PrintOptions, String> {
+        let path = path.as_ref();
+        let mut config = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut config))
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        PrintOptions::from_config(&config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "indent" => {
+                self.indent = match value {
+                    "tab" => Indent::Tab,
+                    _ => Indent::Spaces(parse_value(key, value)?),
+                }
+            }
+            "space_before_colon" => self.space_before_colon = parse_value(key, value)?,
+            "space_after_colon" => self.space_after_colon = parse_value(key, value)?,
+            "blank_lines_between_entities" => self.blank_lines_between_entities = parse_value(key, value)?,
+            "quote_style" => {
+                self.quote_style = match value {
+                    "double" => QuoteStyle::Double,
+                    "single" => QuoteStyle::Single,
+                    _ => return Err(format!("Invalid value '{}' for quote_style, expected double or single", value)),
+                }
+            }
+            "trailing_newline" => self.trailing_newline = parse_value(key, value)?,
+            "fields_first" => self.fields_first = parse_value(key, value)?,
+            "canonical" => self.canonical = parse_value(key, value)?,
+            "minify" => self.minify = parse_value(key, value)?,
+            "max_width" => {
+                self.max_width = match value {
+                    "none" => None,
+                    _ => Some(parse_value(key, value)?),
+                }
+            }
+            _ => return Err(format!("Unknown option '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn parse_value<T: ::std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, key))
+}
 
 pub fn print(pr: ParseResult ) -> String {
-    let mut res = String::new();
-    for child_id in &pr.root.children {
+    print_with_options(&pr, &PrintOptions::default())
+}
+
+pub fn print_with_options(pr: &ParseResult, options: &PrintOptions) -> String {
+    let mut res = Vec::new();
+    write_document(&mut res, pr, options).expect("writing to a Vec can't fail");
+    String::from_utf8(res).unwrap()
+}
+
+/// Writes the whole document to `out` one line at a time, without building it up in memory first.
+pub fn write_document<W: Write>(out: &mut W, pr: &ParseResult, options: &PrintOptions) -> io::Result<()> {
+    let count = pr.root.children.len();
+    for (i, child_id) in pr.root.children.iter().enumerate() {
+        if i > 0 {
+            out.write_all("\n".repeat(options.blank_lines_between_entities).as_bytes())?;
+        }
         let child = pr.get_entity(*child_id);
-        let child_str = print_entity(child, 0, &pr);
-        res.push_str(&child_str);
+        write_entity_inner(out, child, 0, pr, options, i + 1 < count || options.trailing_newline)?;
     }
-    return res;
+    Ok(())
 }
 
-fn print_entity(entity: &AstEntityNode, indent: usize,pr: &ParseResult ) -> String {
-    let mut res = print_entity_header(&entity, indent);
-    res += &print_entity_body(&entity, indent + 1, pr);
-    return res;
+/// Prints an entity, field or expression of the document on its own. Entities and fields end with a
+/// newline, expressions don't.
+pub fn print_node(pr: &ParseResult, node: NodeRef, options: &PrintOptions) -> String {
+    let mut res = Vec::new();
+    write_node(&mut res, pr, node, options).expect("writing to a Vec can't fail");
+    String::from_utf8(res).unwrap()
+}
+
+pub fn write_node<W: Write>(out: &mut W, pr: &ParseResult, node: NodeRef, options: &PrintOptions) -> io::Result<()> {
+    match node {
+        NodeRef::Entity(entity) => write_entity(out, pr.get_entity(entity), 0, pr, options),
+        NodeRef::Field(field) => out.write_all(print_field_with_options(pr.get_field(field), 0, pr, options).as_bytes()),
+        NodeRef::Expr(expr) => out.write_all(print_expr_with_options(pr.get_expr(expr), pr, options).as_bytes()),
+    }
+}
+
+pub fn print_entity(entity: &AstEntityNode, indent: usize,pr: &ParseResult ) -> String {
+    print_entity_with_options(entity, indent, pr, &PrintOptions::default())
+}
+
+/// Prints an entity `indent` levels deep, ending with a newline.
+pub fn print_entity_with_options(entity: &AstEntityNode, indent: usize, pr: &ParseResult,
+                                 options: &PrintOptions) -> String {
+    let mut res = Vec::new();
+    write_entity(&mut res, entity, indent, pr, options).expect("writing to a Vec can't fail");
+    String::from_utf8(res).unwrap()
+}
+
+pub fn write_entity<W: Write>(out: &mut W, entity: &AstEntityNode, indent: usize, pr: &ParseResult,
+                              options: &PrintOptions) -> io::Result<()> {
+    write_entity_inner(out, entity, indent, pr, options, true)
+}
+
+// `newline` is false for the last entity of a document printed without a trailing newline
+fn write_entity_inner<W: Write>(out: &mut W, entity: &AstEntityNode, indent: usize, pr: &ParseResult,
+                                options: &PrintOptions, newline: bool) -> io::Result<()> {
+    out.write_all(create_indent(indent, options).as_bytes())?;
+    out.write_all(entity_header(entity, options).as_bytes())?;
+    out.write_all(b"{\n")?;
+
+    let mut fields: Vec<&AstFieldNode> = entity.fields.iter().map(|f| pr.get_field(*f)).collect();
+    if options.canonical && !has_repeated_fields(&fields) {
+        fields.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    }
+    if options.fields_first {
+        write_fields(out, &fields, indent + 1, pr, options)?;
+        write_children(out, entity, indent + 1, pr, options)?;
+    } else {
+        write_children(out, entity, indent + 1, pr, options)?;
+        write_fields(out, &fields, indent + 1, pr, options)?;
+    }
+
+    out.write_all(create_indent(indent, options).as_bytes())?;
+    out.write_all(if newline { b"}\n" } else { b"}" })
+}
+
+// fields whose order can matter, so canonical mode keeps it
+fn has_repeated_fields(fields: &[&AstFieldNode]) -> bool {
+    fields.iter().enumerate().any(|(i, field)| fields[..i].iter().any(|f| f.identifier == field.identifier))
+}
+
+fn write_fields<W: Write>(out: &mut W, fields: &[&AstFieldNode], indent: usize, pr: &ParseResult,
+                          options: &PrintOptions) -> io::Result<()> {
+    for field in fields {
+        out.write_all(print_field_with_options(field, indent, pr, options).as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_children<W: Write>(out: &mut W, entity: &AstEntityNode, indent: usize, pr: &ParseResult,
+                            options: &PrintOptions) -> io::Result<()> {
+    for (i, child_id) in entity.children.iter().enumerate() {
+        if i > 0 {
+            out.write_all("\n".repeat(options.blank_lines_between_entities).as_bytes())?;
+        }
+        write_entity(out, pr.get_entity(*child_id), indent, pr, options)?;
+    }
+    Ok(())
+}
+
+/// What `print_hierarchy` shows next to each entity.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HierarchyOptions {
+    pub field_count: bool,
+    /// fields to show with their values, by name
+    pub fields: Vec<String>,
+}
+
+/// Draws the entities of the document as a tree, one entity per line:
+///
+/// ```text
+/// page overview
+/// ├── widget kpi #kpi1 (2 fields)
+/// │   └── tile
+/// └── widget account
+/// ```
+pub fn print_hierarchy(pr: &ParseResult, options: &HierarchyOptions) -> String {
+    let mut res = Vec::new();
+    write_hierarchy(&mut res, pr, options).expect("writing to a Vec can't fail");
+    String::from_utf8(res).unwrap()
+}
+
+pub fn write_hierarchy<W: Write>(out: &mut W, pr: &ParseResult, options: &HierarchyOptions) -> io::Result<()> {
+    for child_id in &pr.root.children {
+        write_hierarchy_entity(out, pr.get_entity(*child_id), "", "", pr, options)?;
+    }
+    Ok(())
+}
+
+// `branch` goes in front of the entity's own line and `prefix` in front of the lines below it
+fn write_hierarchy_entity<W: Write>(out: &mut W, entity: &AstEntityNode, branch: &str, prefix: &str,
+                                    pr: &ParseResult, options: &HierarchyOptions) -> io::Result<()> {
+    let mut line = format!("{}{}", branch, print_entity_header(entity, 0).trim_end());
+    if options.field_count {
+        let count = entity.fields.len();
+        line.push_str(&format!(" ({} field{})", count, if count == 1 { "" } else { "s" }));
+    }
+    let shown: Vec<String> = entity.fields.iter()
+        .map(|f| pr.get_field(*f))
+        .filter(|f| options.fields.contains(&f.identifier))
+        .map(|f| print_field(f, 0, pr).trim_end().to_string())
+        .collect();
+    if !shown.is_empty() {
+        line.push_str(&format!(" {{{}}}", shown.join(", ")));
+    }
+    line.push('\n');
+    out.write_all(line.as_bytes())?;
+
+    for (i, child_id) in entity.children.iter().enumerate() {
+        let last = i + 1 == entity.children.len();
+        let (branch, continuation) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        write_hierarchy_entity(out, pr.get_entity(*child_id), &format!("{}{}", prefix, branch),
+                               &format!("{}{}", prefix, continuation), pr, options)?;
+    }
+    Ok(())
+}
+
+fn entity_header(header: &AstEntityNode, options: &PrintOptions) -> String {
+    if !options.minify {
+        return print_entity_header(header, 0);
+    }
+    // `#` and `@` end the identifier before them, so only the sub type needs a space
+    let mut res = header.main_type.clone();
+    if let Some(ref sub_type) = header.sub_type {
+        res.push(' ');
+        res.push_str(sub_type);
+    }
+    if let Some(ref identifier) = header.identifier {
+        res.push('#');
+        res.push_str(identifier);
+    }
+    if let Some(ref reference) = header.reference {
+        res.push('@');
+        res.push_str(reference);
+    }
+    res
 }
 
-fn print_entity_header(header: &AstEntityNode, indent: usize) -> String {
-    let indent = create_indent(indent);
+pub fn print_entity_header(header: &AstEntityNode, indent: usize) -> String {
+    let indent = create_indent(indent, &PrintOptions::default());
     let mut res = "".to_string();
     res.push_str(&indent);
     res.push_str(&header.main_type);
@@ -55,76 +374,147 @@ fn print_entity_header(header: &AstEntityNode, indent: usize) -> String {
     return res;
 }
 
-fn print_entity_body(body: &AstEntityNode, indent: usize, pr: &ParseResult) -> String {
-    let mut res = "{\n".to_string();
-
-    for field_id in &body.fields {
-        let field = pr.get_field(*field_id);
-        res.push_str(&print_field(field, indent + 1, pr));
-    }
-
-    for child_id in &body.children {
-        let child = pr.get_entity(*child_id);
-        res.push_str(&print_entity(child, indent + 1,pr));
-    }
-    res.push_str(&create_indent(indent - 1));
-    res.push_str("}\n");
-    return res;
+pub fn print_field(field: &AstFieldNode, indent: usize, pr : &ParseResult) -> String {
+    print_field_with_options(field, indent, pr, &PrintOptions::default())
 }
 
-fn print_field(field: &AstFieldNode, indent: usize, pr : &ParseResult) -> String {
+pub fn print_field_with_options(field: &AstFieldNode, indent: usize, pr: &ParseResult, options: &PrintOptions) -> String {
     let mut res = "".to_string();
 
-    res.push_str(&create_indent(indent));
+    res.push_str(&create_indent(indent, options));
     res.push_str(&field.identifier);
-    res.push_str(": ");
+    if options.space_before_colon {
+        res.push(' ');
+    }
+    res.push(':');
+    if options.space_after_colon {
+        res.push(' ');
+    }
     let expr = pr.get_expr(field.value);
-    res.push_str(&print_expr(expr, pr));
+    let doc = expr_doc(expr, pr, options, 0, false);
+    let column = width(&res);
+    res.push_str(&render(&doc, options.max_width, column, indent, &indent_unit(options)));
     res.push_str("\n");
     res
 }
 
-fn print_expr(expr: &Expr, pr : &ParseResult) -> String {
-    let mut res = "".to_string();
+pub fn print_expr(expr: &Expr, pr : &ParseResult) -> String {
+    print_expr_with_options(expr, pr, &PrintOptions::default())
+}
 
+pub fn print_expr_with_options(expr: &Expr, pr: &ParseResult, options: &PrintOptions) -> String {
+    render(&expr_doc(expr, pr, options, 0, false), options.max_width, 0, 0, &indent_unit(options))
+}
+
+fn operator_precedence(operator: char) -> u8 {
+    match operator {
+        '*' | '/' => 2,
+        _ => 1,
+    }
+}
+
+// Lays out an expression with just the parentheses needed to parse back to the same tree.
+// `min_prec` is the lowest operator precedence that can be printed without parentheses here, and
+// `before_term_op` tells if the text will be followed by a `*` or `/`, which a unary minus would swallow.
+fn expr_doc(expr: &Expr, pr : &ParseResult, options: &PrintOptions, min_prec: u8, before_term_op: bool) -> Doc {
     match expr {
         Expr::Operator(node) => {
-            let left_node = pr.get_expr(node.left_side);
-            let right_node = pr.get_expr(node.right_side);
-            let left_side = print_expr(left_node, pr);
-            let right_side = print_expr(right_node,pr);
-            res.push_str(&left_side);
-            res.push_str(" ");
-            res.push(node.operator);
-            res.push_str(" ");
-            res.push_str(&right_side);
+            let parens = operator_precedence(node.operator) < min_prec;
+            let chain = operator_chain(node, pr, options, before_term_op && !parens);
+            if parens {
+                group(Doc::Concat(vec![text("("), chain, text(")")]))
+            } else {
+                group(chain)
+            }
         }
+        Expr::UnaryOperator(node) => {
+            // the operand of a unary minus is a whole term
+            let expr = pr.get_expr(node.expr);
+            let doc = Doc::Concat(vec![text(node.operator.to_string()), expr_doc(expr, pr, options, 2, false)]);
+            if before_term_op {
+                Doc::Concat(vec![text("("), doc, text(")")])
+            } else {
+                doc
+            }
+        }
+        Expr::Function(node) if node.argument_list.is_empty() => text(format!("{}()", function_name(&node.identifier, options))),
+        Expr::Function(node) => {
+            // breaks into one argument per line, with the closing parenthesis back on the call's level
+            let mut args = vec![Doc::SoftLine];
+            for (i, arg) in node.argument_list.iter().enumerate() {
+                if i > 0 {
+                    args.push(text(","));
+                    args.push(space(options));
+                }
+                args.push(expr_doc(pr.get_expr(*arg), pr, options, 0, false));
+            }
+            group(Doc::Concat(vec![
+                text(format!("{}(", function_name(&node.identifier, options))),
+                nest(Doc::Concat(args)),
+                Doc::SoftLine,
+                text(")"),
+            ]))
+        }
+        _ => text(print_atom(expr, options)),
+    }
+}
+
+// The operands of a chain of operators with the same precedence, which breaks after each operator.
+fn operator_chain(node: &AstOperatorNode, pr: &ParseResult, options: &PrintOptions, before_term_op: bool) -> Doc {
+    let prec = operator_precedence(node.operator);
+    let left_node = pr.get_expr(node.left_side);
+    let right_node = pr.get_expr(node.right_side);
+    // operators are left associative, so an equal operator on the left continues the chain and an
+    // equal operator on the right needs parentheses
+    let left_side = match left_node {
+        Expr::Operator(left) if operator_precedence(left.operator) == prec => operator_chain(left, pr, options, prec == 2),
+        _ => expr_doc(left_node, pr, options, prec, prec == 2),
+    };
+    let right_side = expr_doc(right_node, pr, options, prec + 1, before_term_op);
+    // a line break ends a field, so the chain breaks after the operators
+    Doc::Concat(vec![
+        left_side,
+        text(if options.minify { node.operator.to_string() } else { format!(" {}", node.operator) }),
+        nest(Doc::Concat(vec![space(options), right_side])),
+    ])
+}
+
+// a space that is left out when minifying, where a line may break
+fn space(options: &PrintOptions) -> Doc {
+    if options.minify {
+        Doc::SoftLine
+    } else {
+        Doc::Line
+    }
+}
+
+fn print_atom(expr: &Expr, options: &PrintOptions) -> String {
+    let mut res = "".to_string();
+
+    match expr {
         Expr::Identifier(node) => {
             res.push_str(&node.value);
         }
         Expr::String(node) => {
-            res.push_str("\"");
+            // there are no escapes in strings, so a string holding the preferred quote keeps the other one
+            let (preferred, other) = match options.quote_style {
+                QuoteStyle::Double => ('"', '\''),
+                QuoteStyle::Single => ('\'', '"'),
+            };
+            let quote = if node.value.contains(preferred) { other } else { preferred };
+            res.push(quote);
             res.push_str(&node.value);
-            res.push_str("\"");
+            res.push(quote);
         }
-        Expr::UnaryOperator(node) => {
-            res.push(node.operator);
-            let expr = pr.get_expr(node.expr);
-            res.push_str(&print_expr(expr, pr));
+        Expr::Number(node) if options.canonical || options.minify => {
+            res.push_str(&normalise_number(&node.text_rep));
         }
         Expr::Number(node) => {
             res.push_str(&node.text_rep);
         }
-        Expr::Function(node) => {
-            let mut arg_list = Vec::new();
-            res.push_str(&node.identifier);
-            res.push('(');
-            for arg in &node.argument_list {
-                let expr = pr.get_expr(*arg);
-                arg_list.push(print_expr(expr, pr));
-            }
-            res.push_str(&(arg_list.join(", ")));
-            res.push(')');
+        Expr::Reference(node) => {
+            res.push('@');
+            res.push_str(&node.value);
         }
         Expr::VPath(node) => {
             match node.table {
@@ -155,19 +545,64 @@ fn print_expr(expr: &Expr, pr : &ParseResult) -> String {
                 _ => {}
             }
         }
+        Expr::Operator(_) | Expr::UnaryOperator(_) | Expr::Function(_) => {}
     }
     res
 }
 
-fn create_indent(indent: usize) -> String {
-    std::iter::repeat(" ").take(indent * 2).collect::<String>()
+// functions whose names are case insensitive, printed upper-case in canonical form. These are the
+// aggregate and condition functions the scripts in cdl-scripts use, some of them in both cases, like
+// `average` and `AVERAGE`. Other functions keep the case they are written in.
+const KNOWN_FUNCTIONS: &[&str] = &["AVERAGE", "COUNT", "IIF", "MAX", "MIN", "NPS", "SCORE", "SUM"];
+
+fn function_name(identifier: &str, options: &PrintOptions) -> String {
+    let upper = identifier.to_uppercase();
+    if options.canonical && KNOWN_FUNCTIONS.contains(&upper.as_str()) {
+        upper
+    } else {
+        identifier.to_string()
+    }
+}
+
+// 007.500 -> 7.5, 1. -> 1
+fn normalise_number(text: &str) -> String {
+    let (integer, fraction) = match text.find('.') {
+        Some(dot) => (&text[..dot], text[dot + 1..].trim_end_matches('0')),
+        None => (text, ""),
+    };
+    let integer = match integer.trim_start_matches('0') {
+        "" => "0",
+        trimmed => trimmed,
+    };
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+fn create_indent(indent: usize, options: &PrintOptions) -> String {
+    indent_unit(options).repeat(indent)
+}
+
+fn indent_unit(options: &PrintOptions) -> String {
+    match options.indent {
+        Indent::Spaces(width) => " ".repeat(width),
+        Indent::Tab => "\t".to_string(),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use lex::Lexer;
-    use parse::Parser;
+    use parse::{AstFunctionNode, AstIdentifierNode, AstNumberNode, AstOperatorNode, AstUnaryOperatorNode,
+                EntityExprRef, Expr, ParseResult, Parser};
     use print;
+    use print::{HierarchyOptions, Indent, PrintOptions, QuoteStyle};
+    use std::fs::File;
+    use std::io::Read;
+    use parse::NodeRef;
+    use structural::{exprs_equal, nodes_equal, StructuralOptions};
 
     const EXPR_CDL: &str = "widget kpi   {
     expr1: 1 + 1
@@ -197,7 +632,7 @@ mod test {
     expr1: 1 * -1
     expr1: 1 - 1
     expr1: 1 + 1 + 1 + 1
-    expr1: 1 + 1 + 1 + 1
+    expr1: 1 + (1 + 1) + 1
     expr1: s1
     expr1: s1:q1
     expr1: NPS(s1:q1)
@@ -258,4 +693,379 @@ mod test {
 ".to_string();
         assert_eq!(out, correct);
     }
+
+    #[test]
+    fn print_minimal_parentheses() {
+        let cdl = "widget kpi {
+    expr: (a - b) * c
+    expr: a - (b - c)
+    expr: a - b - c
+    expr: a / (b * c)
+    expr: (@cr.rateResponses/@cr.rateInvites)*100
+    expr: (-a) * b
+    expr: -(a * b)
+    expr: a * (-b) * c
+    expr: a * -b
+    expr: -(a - b) + c
+    expr: MAX((a + b) * 2, (c))
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+        let out = print::print(root);
+        let correct = "widget kpi {
+    expr: (a - b) * c
+    expr: a - (b - c)
+    expr: a - b - c
+    expr: a / (b * c)
+    expr: @cr.rateResponses / @cr.rateInvites * 100
+    expr: (-a) * b
+    expr: -a * b
+    expr: a * (-b) * c
+    expr: a * -b
+    expr: -(a - b) + c
+    expr: MAX((a + b) * 2, c)
+}
+".to_string();
+        assert_eq!(out, correct);
+    }
+
+    #[test]
+    fn print_with_options() {
+        let cdl = "page #overview {
+    widget kpi {
+        label: 'It\"s'
+    }
+    widget kpi {
+        label: \"KPI\"
+    }
+    title: \"Overview\"
+}
+page {
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        let options = PrintOptions {
+            indent: Indent::Tab,
+            space_before_colon: true,
+            blank_lines_between_entities: 1,
+            quote_style: QuoteStyle::Single,
+            trailing_newline: false,
+            ..PrintOptions::default()
+        };
+        let correct = "page #overview {
+\ttitle : 'Overview'
+\twidget kpi {
+\t\tlabel : 'It\"s'
+\t}
+
+\twidget kpi {
+\t\tlabel : 'KPI'
+\t}
+}
+
+page {
+}";
+        assert_eq!(print::print_with_options(&root, &options), correct);
+
+        let options = PrintOptions {
+            indent: Indent::Spaces(2),
+            space_after_colon: false,
+            fields_first: false,
+            ..PrintOptions::default()
+        };
+        assert!(print::print_with_options(&root, &options).starts_with("page #overview {
+  widget kpi {
+    label:'It\"s'
+  }
+  widget kpi {
+    label:\"KPI\"
+  }
+  title:\"Overview\"
+}
+"));
+    }
+
+    #[test]
+    fn print_options_from_config() {
+        let config = "
+# team style
+indent = tab
+space_before_colon = true   # like the old scripts
+blank_lines_between_entities = 2
+quote_style = single
+";
+        let options = PrintOptions::from_config(config).unwrap();
+        assert_eq!(options, PrintOptions {
+            indent: Indent::Tab,
+            space_before_colon: true,
+            blank_lines_between_entities: 2,
+            quote_style: QuoteStyle::Single,
+            ..PrintOptions::default()
+        });
+        assert_eq!(PrintOptions::from_config("indent = 2").unwrap().indent, Indent::Spaces(2));
+        assert_eq!(PrintOptions::from_config("max_width = 100").unwrap().max_width, Some(100));
+        assert_eq!(PrintOptions::from_config("\nindent = wide").unwrap_err(), "Line 2: Invalid value 'wide' for indent");
+        assert_eq!(PrintOptions::from_config("colour = red").unwrap_err(), "Line 1: Unknown option 'colour'");
+        assert!(PrintOptions::from_config("trailing_newline").is_err());
+    }
+
+    #[test]
+    fn print_long_expressions_within_max_width() {
+        let cdl = "widget kpi {
+    short: IIF(a, 1, 2)
+    value: IIF(score(survey:Q1), \"Very satisfied\", IIF(score(survey:Q2), \"Satisfied\", \"Unsatisfied\"))
+    total: @cr.responsesFirstQuarter + @cr.responsesSecondQuarter - (@cr.responsesThirdQuarter * 2)
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+        let options = PrintOptions {
+            max_width: Some(60),
+            ..PrintOptions::default()
+        };
+        let out = print::print_with_options(&root, &options);
+        let correct = "widget kpi {
+    short: IIF(a, 1, 2)
+    value: IIF(
+        score(survey:Q1),
+        \"Very satisfied\",
+        IIF(score(survey:Q2), \"Satisfied\", \"Unsatisfied\")
+    )
+    total: @cr.responsesFirstQuarter +
+        @cr.responsesSecondQuarter -
+        @cr.responsesThirdQuarter * 2
+}
+";
+        assert_eq!(out, correct);
+        assert!(out.lines().all(|line| line.len() <= 60));
+
+        // the broken lines parse back to the same document
+        let reparsed = Parser::new(Lexer::new(out).lex().unwrap()).parse().unwrap();
+        assert!(nodes_equal(&root, NodeRef::Entity(0), &reparsed, NodeRef::Entity(0), StructuralOptions::default()));
+    }
+
+    #[test]
+    fn print_canonical() {
+        let cdl = "widget kpi #id {
+    value: average(score(survey:Q7), 007.500) + Max(a, 1.) + CalendarMonth(1234.001000)
+    label : 'Label'
+    format: formatterLTR
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+        let out = print::print_with_options(&root, &PrintOptions::canonical());
+        let correct = "widget kpi #id {
+    format: formatterLTR
+    label: \"Label\"
+    value: AVERAGE(SCORE(survey:Q7), 7.5) + MAX(a, 1) + CalendarMonth(1234.001)
+}
+";
+        assert_eq!(out, correct);
+
+        let reparsed = Parser::new(Lexer::new(out.clone()).lex().unwrap()).parse().unwrap();
+        assert_eq!(print::print_with_options(&reparsed, &PrintOptions::canonical()), out);
+        // a repeated field keeps the order of the fields
+        let repeated = "widget kpi {\n    value: 1\n    label: \"B\"\n    label: \"A\"\n}\n";
+        assert_eq!(::format(repeated.to_string(), &PrintOptions::canonical()).unwrap(), repeated);
+
+        let script = include_str!("../../cdl-scripts/simple.cdl").to_string();
+        let once = ::format(script, &PrintOptions::canonical()).unwrap();
+        assert_eq!(::format(once.clone(), &PrintOptions::canonical()).unwrap(), once);
+        assert_eq!(print::normalise_number("0.0"), "0");
+        assert_eq!(print::normalise_number("10"), "10");
+    }
+
+    #[test]
+    fn print_single_nodes() {
+        let cdl = "page #overview {
+    widget kpi #kpi1 {
+        value: average(score(survey:Q7))
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl.clone());
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let pr = parser.parse().unwrap();
+        let options = PrintOptions::default();
+
+        let page = pr.get_entity(pr.root.children[0]);
+        assert_eq!((page.sub_type.as_deref(), page.identifier.as_deref()), (None, Some("overview")));
+        assert_eq!(print::print_node(&pr, NodeRef::Entity(pr.root.children[0]), &options), cdl);
+        let widget = page.children[0];
+        let field = pr.get_entity(widget).fields[0];
+        let value = pr.get_field(field).value;
+        assert_eq!(print::print_node(&pr, NodeRef::Entity(widget), &options), "widget kpi #kpi1 {
+    value: average(score(survey:Q7))
+}
+");
+        assert_eq!(print::print_node(&pr, NodeRef::Field(field), &options), "value: average(score(survey:Q7))\n");
+        assert_eq!(print::print_node(&pr, NodeRef::Expr(value), &options), "average(score(survey:Q7))");
+
+        let mut out = Vec::new();
+        print::write_document(&mut out, &pr, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), cdl);
+    }
+
+    #[test]
+    fn print_minified() {
+        let cdl = "widget kpi #kpi1 @default {
+    // comments go
+    value: average(score(survey:Q7), 2.50) * (1 - -x) / 100.0
+    label: \"KPI\"
+    tile {
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+        assert_eq!(print::print_with_options(&root, &PrintOptions::minified()),
+                   "widget kpi#kpi1@default{\nvalue:average(score(survey:Q7),2.5)*(1--x)/100\nlabel:\"KPI\"\ntile{\n}\n}\n");
+    }
+
+    #[test]
+    fn minified_scripts_compile_to_the_same_document() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../cdl-scripts");
+        let mut checked = Vec::new();
+        for entry in ::std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let mut script = String::new();
+            File::open(&path).unwrap().read_to_string(&mut script).unwrap();
+            // some scripts use syntax the parser doesn't support yet, there is nothing to minify
+            let original = match ::compile(script.clone()) {
+                Ok(original) => original,
+                Err(_) => continue,
+            };
+            let minified = ::minify(script.clone()).unwrap();
+            assert!(minified.len() < script.len());
+            let compiled = ::compile(minified).unwrap();
+            assert_eq!(compiled.root.children.len(), original.root.children.len());
+            for (a, b) in original.root.children.iter().zip(&compiled.root.children) {
+                assert!(nodes_equal(&original, NodeRef::Entity(*a), &compiled, NodeRef::Entity(*b),
+                                    StructuralOptions::default()), "{} changed when minified", path.display());
+            }
+            checked.push(name);
+        }
+        assert!(checked.contains(&"simple.cdl".to_string()), "only checked {:?}", checked);
+    }
+
+    #[test]
+    fn print_entity_hierarchy() {
+        let cdl = "page overview {
+    layout column {
+        layout row {
+            tile kpi #t1 @default {
+                label: \"KPI\"
+                value: 1
+            }
+        }
+        tile {
+        }
+    }
+    widget account {
+        label: \"Account\"
+    }
+}
+config report cr {
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        assert_eq!(print::print_hierarchy(&root, &HierarchyOptions::default()), "page overview
+├── layout column
+│   ├── layout row
+│   │   └── tile kpi #t1 @default
+│   └── tile
+└── widget account
+config report #cr
+");
+
+        let options = HierarchyOptions {
+            field_count: true,
+            fields: vec!["label".to_string()],
+        };
+        assert!(print::print_hierarchy(&root, &options).contains("│   │   └── tile kpi #t1 @default (2 fields) {label: \"KPI\"}\n"));
+    }
+
+    // small linear congruential generator, so the property test is repeatable without extra crates
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn random_expr(pr: &mut ParseResult, rnd: &mut Random, depth: u32) -> EntityExprRef {
+        let kind = if depth == 0 { rnd.next(2) } else { rnd.next(6) };
+        let node = match kind {
+            0 => {
+                let value = rnd.next(100);
+                Expr::Number(Box::new(AstNumberNode::new(value as f64, value.to_string())))
+            }
+            1 => Expr::Identifier(Box::new(AstIdentifierNode { value: format!("x{}", rnd.next(10)) })),
+            2 => {
+                let expr = random_expr(pr, rnd, depth - 1);
+                Expr::UnaryOperator(Box::new(AstUnaryOperatorNode { operator: '-', expr }))
+            }
+            3 => {
+                let argument_list = (0..rnd.next(3)).map(|_| random_expr(pr, rnd, depth - 1)).collect();
+                Expr::Function(Box::new(AstFunctionNode { identifier: "F".to_string(), argument_list }))
+            }
+            _ => {
+                let left_side = random_expr(pr, rnd, depth - 1);
+                let right_side = random_expr(pr, rnd, depth - 1);
+                let operator = ['+', '-', '*', '/'][rnd.next(4) as usize];
+                Expr::Operator(Box::new(AstOperatorNode { operator, left_side, right_side }))
+            }
+        };
+        pr.add_expr(node)
+    }
+
+    #[test]
+    fn print_then_parse_is_structurally_equal() {
+        let mut rnd = Random(0x5eed);
+        for _ in 0..2000 {
+            let mut pr = ParseResult::new();
+            let expr = random_expr(&mut pr, &mut rnd, 5);
+            let text = print::print_expr(pr.get_expr(expr), &pr);
+            assert_reparses(&pr, expr, &text);
+
+            let narrow = PrintOptions {
+                max_width: Some(12),
+                ..PrintOptions::default()
+            };
+            let text = print::print_expr_with_options(pr.get_expr(expr), &pr, &narrow);
+            assert_reparses(&pr, expr, &text);
+
+            let text = print::print_expr_with_options(pr.get_expr(expr), &pr, &PrintOptions::minified());
+            assert_reparses(&pr, expr, &text);
+        }
+    }
+
+    fn assert_reparses(pr: &ParseResult, expr: EntityExprRef, text: &str) {
+        let mut reparsed = ParseResult::new();
+        let parser = Parser::new(Lexer::new(text.to_string()).lex().unwrap());
+        let reparsed_expr = parser.parse_expression(&mut reparsed).unwrap();
+        assert!(exprs_equal(pr, expr, &reparsed, reparsed_expr), "{} did not parse back to the same tree", text);
+    }
 }
@@ -0,0 +1,187 @@
+use parse::{Expr, ParseResult};
+use print::print_expr;
+use select::parse::SelectorError;
+
+// Selectors for expression nodes, a comma separated list of kinds with an optional pattern:
+//
+//     fn(COUNT)         calls to COUNT, function names ignore case
+//     vpath(survey:*)   vpaths as printed, `*` matching any text
+//     ref(cr.*)         references, without the `@`
+//     str(Acc*)         string literals, without the quotes
+//     id(formatter*)    identifiers
+//     num(>1000)        number literals compared with `>`, `>=`, `<`, `<=` or `=`
+//
+// A kind without a pattern, like `fn`, matches every node of that kind.
+
+#[derive(Debug, PartialEq)]
+pub enum ExprSelector {
+    Function(String),
+    VPath(String),
+    Reference(String),
+    String(String),
+    Identifier(String),
+    Number(Comparison, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    MoreOrEqual,
+    More,
+}
+
+// Errors are located by the byte offset in `selector`, like those of entity selectors
+pub fn parse_expr_selectors(selector: &str) -> Result<Vec<ExprSelector>, SelectorError> {
+    let error = |message: String, position: usize| Err(SelectorError { message, position });
+    let mut selectors = Vec::new();
+    let mut rest = selector.trim_start();
+    while !rest.is_empty() {
+        let start = selector.len() - rest.len();
+        let kind_end = rest.find(&['(', ','][..]).unwrap_or(rest.len());
+        let kind = rest[..kind_end].trim();
+        rest = &rest[kind_end..];
+        let pattern = if rest.starts_with('(') {
+            let close = match rest.find(')') {
+                Some(close) => close,
+                None => return error(format!("Missing ')' after {}(", kind), selector.len()),
+            };
+            let pattern = rest[1..close].trim();
+            rest = &rest[close + 1..];
+            pattern
+        } else {
+            "*"
+        };
+        match expr_selector(kind, pattern) {
+            Ok(expr_selector) => selectors.push(expr_selector),
+            Err(message) => return error(message, start),
+        }
+
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+            if rest.is_empty() {
+                return error("Expected an expression selector after ','".to_string(), selector.len());
+            }
+        } else if !rest.is_empty() {
+            let position = selector.len() - rest.len();
+            return error(format!("Expected ',' between expression selectors, got {}", rest), position);
+        }
+    }
+    if selectors.is_empty() {
+        return error("Empty expression selector".to_string(), 0);
+    }
+    Ok(selectors)
+}
+
+fn expr_selector(kind: &str, pattern: &str) -> Result<ExprSelector, String> {
+    let pattern = pattern.to_string();
+    match kind {
+        "fn" => Ok(ExprSelector::Function(pattern)),
+        "vpath" => Ok(ExprSelector::VPath(pattern)),
+        "ref" => Ok(ExprSelector::Reference(pattern.trim_start_matches('@').to_string())),
+        "str" => Ok(ExprSelector::String(pattern)),
+        "id" => Ok(ExprSelector::Identifier(pattern)),
+        "num" => {
+            if pattern == "*" {
+                return Ok(ExprSelector::Number(Comparison::MoreOrEqual, f64::NEG_INFINITY));
+            }
+            let comparisons = [(">=", Comparison::MoreOrEqual), ("<=", Comparison::LessOrEqual), (">", Comparison::More),
+                               ("<", Comparison::Less), ("=", Comparison::Equal)];
+            let (comparison, number) = comparisons.iter()
+                .filter_map(|&(prefix, comparison)| pattern.strip_prefix(prefix).map(|number| (comparison, number)))
+                .next()
+                .unwrap_or((Comparison::Equal, &pattern));
+            let number = number.trim().parse::<f64>().map_err(|_| format!("Expected a number in num({})", pattern))?;
+            Ok(ExprSelector::Number(comparison, number))
+        }
+        _ => Err(format!("Unknown expression kind {}, expected fn, vpath, ref, str, id or num", kind)),
+    }
+}
+
+pub fn matches_expr(expr: &Expr, selector: &ExprSelector, pr: &ParseResult) -> bool {
+    match (expr, selector) {
+        (Expr::Function(node), ExprSelector::Function(pattern)) => {
+            glob_match(&pattern.to_lowercase(), &node.identifier.to_lowercase())
+        }
+        (Expr::VPath(_), ExprSelector::VPath(pattern)) => glob_match(pattern, &print_expr(expr, pr)),
+        (Expr::Reference(node), ExprSelector::Reference(pattern)) => glob_match(pattern, &node.value),
+        (Expr::String(node), ExprSelector::String(pattern)) => glob_match(pattern, &node.value),
+        (Expr::Identifier(node), ExprSelector::Identifier(pattern)) => glob_match(pattern, &node.value),
+        (Expr::Number(node), &ExprSelector::Number(comparison, number)) => match comparison {
+            Comparison::Less => node.value < number,
+            Comparison::LessOrEqual => node.value <= number,
+            Comparison::Equal => node.value == number,
+            Comparison::MoreOrEqual => node.value >= number,
+            Comparison::More => node.value > number,
+        },
+        _ => false,
+    }
+}
+
+// `*` matches any text, everything else itself
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !text.starts_with(first) {
+        return false;
+    }
+    let mut rest = &text[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use select::expr::{glob_match, parse_expr_selectors, Comparison, ExprSelector};
+
+    #[test]
+    fn parse_expr_selector_list() {
+        let selectors = parse_expr_selectors("fn(COUNT), vpath(survey:*), ref(@cr.*), num(>1000), fn").unwrap();
+        assert_eq!(selectors, vec![
+            ExprSelector::Function("COUNT".to_string()),
+            ExprSelector::VPath("survey:*".to_string()),
+            ExprSelector::Reference("cr.*".to_string()),
+            ExprSelector::Number(Comparison::More, 1000.0),
+            ExprSelector::Function("*".to_string()),
+        ]);
+        assert_eq!(parse_expr_selectors("num(<= 0.5)").unwrap(), vec![ExprSelector::Number(Comparison::LessOrEqual, 0.5)]);
+
+        let error = |selector: &str| {
+            let e = parse_expr_selectors(selector).unwrap_err();
+            (e.message, e.position)
+        };
+        assert_eq!(error(" "), ("Empty expression selector".to_string(), 0));
+        assert_eq!(error("fn(COUNT"), ("Missing ')' after fn(".to_string(), 8));
+        assert_eq!(error("fn, call(COUNT)").1, 4);
+        assert_eq!(error("num(>big)"), ("Expected a number in num(>big)".to_string(), 0));
+        assert_eq!(error("fn(COUNT) vpath"),
+                   ("Expected ',' between expression selectors, got vpath".to_string(), 10));
+        assert_eq!(error("fn,"), ("Expected an expression selector after ','".to_string(), 3));
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("survey:*", "survey:Q7"));
+        assert!(!glob_match("survey:*", "hub:Q7"));
+        assert!(glob_match("*:Q7", "survey:Q7"));
+        assert!(glob_match("cr.*Period*", "cr.currentPeriodB2b"));
+        assert!(glob_match("a*a", "aa"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(glob_match("COUNT", "COUNT"));
+        assert!(!glob_match("COUNT", "COUNTS"));
+        assert!(glob_match("*", ""));
+    }
+}
@@ -1,11 +1,22 @@
+use std::cell::Cell;
 use std::iter::Peekable;
+use lex::{CountingChars, Span};
 
-pub fn lex_selector(selector: &str) -> Vec<LexItem> {
-    let mut it = selector.chars().peekable();
+/// Splits a selector into tokens with their spans. Characters that are not part of the syntax become
+/// `Unknown` tokens, for the parser to report.
+pub fn lex_selector(selector: &str) -> Vec<(LexItem, Span)> {
+    let pos = Cell::new(0);
+    let mut it = CountingChars::new(selector, &pos).peekable();
     let mut result = Vec::new();
+    let mut spans = Vec::new();
+    // whitespace inside `{}` separates nothing
+    let mut in_predicate = false;
     while let Some(&c) = it.peek() {
+        // the peeked char has already been counted
+        let start = pos.get() - c.len_utf8();
+        let token_count = result.len();
         match c {
-            'a'...'z' | 'A'...'Z' | '_' => {
+            _ if c.is_ascii_alphanumeric() || c == '_' => {
                 it.next();
                 let ident = get_identifier(c, &mut it);
                 result.push(LexItem::Identifier(ident));
@@ -26,16 +37,112 @@ pub fn lex_selector(selector: &str) -> Vec<LexItem> {
                 result.push(LexItem::Arrow);
                 it.next();
             }
+            // the sign of a number in a predicate, like `{size=-1}`
+            '-' | '+' if in_predicate && result.last() == Some(&LexItem::Equal) => {
+                it.next();
+                let value = get_identifier(c, &mut it);
+                result.push(LexItem::Identifier(value));
+            }
+            '<' => {
+                result.push(LexItem::LeftArrow);
+                it.next();
+            }
+            '+' => {
+                result.push(LexItem::Plus);
+                it.next();
+            }
+            ' ' if in_predicate => {
+                it.next();
+            }
             ' ' => {
+                while it.peek() == Some(&' ') {
+                    it.next();
+                }
+                // whitespace is the descendant combinator, unless it is only around the selector or around
+                // punctuation like `>` and `,`
+                let after_punctuation = matches!(result.last(),
+                    None | Some(&LexItem::Arrow) | Some(&LexItem::LeftArrow) | Some(&LexItem::Plus) | Some(&LexItem::Tilde)
+                    | Some(&LexItem::Comma) | Some(&LexItem::OpenPar));
+                let before_punctuation =
+                    matches!(it.peek(), None | Some(&'>') | Some(&'<') | Some(&'+') | Some(&'~') | Some(&',') | Some(&')'));
+                if !after_punctuation && !before_punctuation {
+                    result.push(LexItem::Space);
+                }
+            }
+            '#' => {
+                result.push(LexItem::Hash);
+                it.next();
+            }
+            '@' => {
+                it.next();
+                let reference = get_reference(&mut it);
+                result.push(LexItem::Reference(reference));
+            }
+            ',' => {
+                result.push(LexItem::Comma);
+                it.next();
+            }
+            ':' => {
+                result.push(LexItem::Colon);
+                it.next();
+            }
+            '(' => {
+                result.push(LexItem::OpenPar);
+                it.next();
+            }
+            ')' => {
+                result.push(LexItem::ClosePar);
                 it.next();
             }
+            '{' => {
+                in_predicate = true;
+                result.push(LexItem::OpenBrace);
+                it.next();
+            }
+            '}' => {
+                in_predicate = false;
+                result.push(LexItem::CloseBrace);
+                it.next();
+            }
+            '=' => {
+                result.push(LexItem::Equal);
+                it.next();
+            }
+            '^' => {
+                result.push(LexItem::Caret);
+                it.next();
+            }
+            '$' => {
+                result.push(LexItem::Dollar);
+                it.next();
+            }
+            '*' => {
+                result.push(LexItem::Star);
+                it.next();
+            }
+            '~' => {
+                result.push(LexItem::Tilde);
+                it.next();
+            }
+            '"' | '\'' => {
+                it.next();
+                match get_quoted_string(c, &mut it) {
+                    Some(quoted) => result.push(LexItem::String(quoted)),
+                    None => result.push(LexItem::UnterminatedString),
+                }
+            }
             _ => {
-                println!("Unknown parsing {}", c);
+                result.push(LexItem::Unknown(c));
                 it.next();
             }
         }
+        if result.len() > token_count {
+            // peek before reading the position, peeking may count the next char
+            let peeked = it.peek().map_or(0, |c| c.len_utf8());
+            spans.push(Span { start, end: pos.get() - peeked });
+        }
     }
-    return result;
+    result.into_iter().zip(spans).collect()
 }
 
 
@@ -46,6 +153,58 @@ pub enum LexItem {
     OpenSquare,
     CloseSquare,
     Arrow,
+    LeftArrow,
+    Plus,
+    Space,
+    OpenBrace,
+    CloseBrace,
+    Equal,
+    Caret,
+    Dollar,
+    Star,
+    Tilde,
+    String(String),
+    Comma,
+    Colon,
+    OpenPar,
+    ClosePar,
+    Hash,
+    Reference(String),
+    UnterminatedString,
+    Unknown(char),
+}
+
+impl LexItem {
+    /// How the token is shown in error messages
+    pub fn describe(&self) -> String {
+        let text = match *self {
+            LexItem::Identifier(ref s) => s.to_string(),
+            LexItem::String(ref s) => format!("\"{}\"", s),
+            LexItem::Reference(ref s) => format!("@{}", s),
+            LexItem::UnterminatedString => return "an unterminated string".to_string(),
+            LexItem::Space => return "a space".to_string(),
+            LexItem::Unknown(c) => c.to_string(),
+            LexItem::Dot => ".".to_string(),
+            LexItem::OpenSquare => "[".to_string(),
+            LexItem::CloseSquare => "]".to_string(),
+            LexItem::Arrow => ">".to_string(),
+            LexItem::LeftArrow => "<".to_string(),
+            LexItem::Plus => "+".to_string(),
+            LexItem::OpenBrace => "{".to_string(),
+            LexItem::CloseBrace => "}".to_string(),
+            LexItem::Equal => "=".to_string(),
+            LexItem::Caret => "^".to_string(),
+            LexItem::Dollar => "$".to_string(),
+            LexItem::Star => "*".to_string(),
+            LexItem::Tilde => "~".to_string(),
+            LexItem::Comma => ",".to_string(),
+            LexItem::Colon => ":".to_string(),
+            LexItem::OpenPar => "(".to_string(),
+            LexItem::ClosePar => ")".to_string(),
+            LexItem::Hash => "#".to_string(),
+        };
+        format!("'{}'", text)
+    }
 }
 
 
@@ -54,7 +213,8 @@ fn get_identifier<T: Iterator<Item=char>>(c: char, iter: &mut Peekable<T>) -> St
     identifier.push(c);
     while let Some(&ch) = iter.peek() {
         match ch {
-            'a'...'z' | 'A'...'Z' | '_' | '0'...'9' => {
+            // `-` for names like first-child
+            'a'...'z' | 'A'...'Z' | '_' | '0'...'9' | '-' => {
                 identifier.push(ch);
                 iter.next();
             }
@@ -64,11 +224,47 @@ fn get_identifier<T: Iterator<Item=char>>(c: char, iter: &mut Peekable<T>) -> St
     identifier
 }
 
+// like in CDL, a reference can have dots, as in `@cr.currentPeriod`
+fn get_reference<T: Iterator<Item=char>>(iter: &mut Peekable<T>) -> String {
+    let mut reference = String::new();
+    while let Some(&ch) = iter.peek() {
+        match ch {
+            _ if ch.is_ascii_alphanumeric() || ch == '_' || ch == '.' => {
+                reference.push(ch);
+                iter.next();
+            }
+            _ => { break; }
+        }
+    }
+    reference
+}
+
+// a string up to the closing `quote`, which can be escaped with `\`. Other backslashes are kept, so
+// regular expressions can be written as they are. `None` when the string is not closed.
+fn get_quoted_string<T: Iterator<Item=char>>(quote: char, iter: &mut Peekable<T>) -> Option<String> {
+    let mut quoted = String::new();
+    while let Some(ch) = iter.next() {
+        match ch {
+            '\\' if iter.peek() == Some(&quote) => {
+                quoted.push(quote);
+                iter.next();
+            }
+            _ if ch == quote => return Some(quoted),
+            _ => quoted.push(ch),
+        }
+    }
+    None
+}
+
 
 
 #[cfg(test)]
 mod test {
-    use select::lex::lex_selector;
+    use select::lex::{lex_selector, LexItem};
+
+    fn tokens(selector: &str) -> Vec<LexItem> {
+        lex_selector(selector).into_iter().map(|(token, _)| token).collect()
+    }
 
     #[test]
     fn lex_selector_test() {
@@ -84,4 +280,103 @@ mod test {
         assert_eq!(selector.len(), 8);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn lex_selector_descendant() {
+        let s = " page  widget >  .label ";
+        assert_eq!(tokens(s), vec![
+            LexItem::Identifier("page".to_string()),
+            LexItem::Space,
+            LexItem::Identifier("widget".to_string()),
+            LexItem::Arrow,
+            LexItem::Dot,
+            LexItem::Identifier("label".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn lex_selector_predicates() {
+        let s = "column{format = formatterLTR} {label^=\"Acc \\\"A\\\"\"}";
+        assert_eq!(tokens(s), vec![
+            LexItem::Identifier("column".to_string()),
+            LexItem::OpenBrace,
+            LexItem::Identifier("format".to_string()),
+            LexItem::Equal,
+            LexItem::Identifier("formatterLTR".to_string()),
+            LexItem::CloseBrace,
+            LexItem::Space,
+            LexItem::OpenBrace,
+            LexItem::Identifier("label".to_string()),
+            LexItem::Caret,
+            LexItem::Equal,
+            LexItem::String("Acc \"A\"".to_string()),
+            LexItem::CloseBrace,
+        ]);
+    }
+
+    #[test]
+    fn lex_selector_lists() {
+        let s = "tile , column:not( [metric] , *.c1 )";
+        assert_eq!(tokens(s), vec![
+            LexItem::Identifier("tile".to_string()),
+            LexItem::Comma,
+            LexItem::Identifier("column".to_string()),
+            LexItem::Colon,
+            LexItem::Identifier("not".to_string()),
+            LexItem::OpenPar,
+            LexItem::OpenSquare,
+            LexItem::Identifier("metric".to_string()),
+            LexItem::CloseSquare,
+            LexItem::Comma,
+            LexItem::Star,
+            LexItem::Dot,
+            LexItem::Identifier("c1".to_string()),
+            LexItem::ClosePar,
+        ]);
+    }
+
+    #[test]
+    fn lex_selector_ids_and_references() {
+        let s = "widget#kpi1@cr.base";
+        assert_eq!(tokens(s), vec![
+            LexItem::Identifier("widget".to_string()),
+            LexItem::Hash,
+            LexItem::Identifier("kpi1".to_string()),
+            LexItem::Reference("cr.base".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn lex_selector_parent_and_siblings() {
+        let s = "column < widget ~ widget+tile {label ~= 'x'}";
+        assert_eq!(tokens(s), vec![
+            LexItem::Identifier("column".to_string()),
+            LexItem::LeftArrow,
+            LexItem::Identifier("widget".to_string()),
+            LexItem::Tilde,
+            LexItem::Identifier("widget".to_string()),
+            LexItem::Plus,
+            LexItem::Identifier("tile".to_string()),
+            LexItem::Space,
+            LexItem::OpenBrace,
+            LexItem::Identifier("label".to_string()),
+            LexItem::Tilde,
+            LexItem::Equal,
+            LexItem::String("x".to_string()),
+            LexItem::CloseBrace,
+        ]);
+    }
+
+    #[test]
+    fn lex_selector_spans() {
+        let s = "ø > #id{a='b'} ?";
+        let spans: Vec<&str> = lex_selector(s).iter().map(|&(_, span)| &s[span.start..span.end]).collect();
+        assert_eq!(spans, vec!["ø", ">", "#", "id", "{", "a", "=", "'b'", "}", " ", "?"]);
+        assert_eq!(tokens(s)[0], LexItem::Unknown('ø'));
+        assert_eq!(tokens("{a='b}"), vec![
+            LexItem::OpenBrace,
+            LexItem::Identifier("a".to_string()),
+            LexItem::Equal,
+            LexItem::UnterminatedString,
+        ]);
+    }
+}
@@ -1,107 +1,399 @@
 mod lex;
 mod parse;
+mod expr;
 
 use parse::AstEntityNode;
 use parse::AstFieldNode;
+use parse::{EntityRef, EntityFieldRef, EntityExprRef, NodeRef};
+use edit::expr_children;
+use select::expr::{parse_expr_selectors, matches_expr};
 use select::lex::lex_selector;
-use select::parse::{SelectorParser, Selector};
+use select::parse::{SelectorParser, Combinator, ValueTest, PseudoClass};
+pub use select::parse::{Selector, SelectorError};
+use parse::Expr;
+use print::print_expr;
 use parse::ParseResult;
+use index::DocumentIndex;
+use std::collections::HashSet;
 
 
+/// An entity found by a selector, with the entities it is in, outermost first
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityMatch {
+    pub entity: EntityRef,
+    pub path: Vec<EntityRef>,
+}
+
+/// A field found by a selector, with the entity it is in and the path to that entity
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMatch {
+    pub field: EntityFieldRef,
+    pub entity: EntityRef,
+    pub path: Vec<EntityRef>,
+}
+
+impl EntityMatch {
+    pub fn node(&self) -> NodeRef {
+        NodeRef::Entity(self.entity)
+    }
+}
+
+impl FieldMatch {
+    pub fn node(&self) -> NodeRef {
+        NodeRef::Field(self.field)
+    }
+}
+
+/// The entities matching any of the comma separated selectors, once each and in document order.
+/// Panics on an invalid selector, `Selector::compile` reports it instead.
 pub fn select_entity<'a>(pr: &'a ParseResult, selector_string: &str) -> Vec<&'a AstEntityNode> {
-    let tokens = lex_selector(selector_string);
-    let parser = SelectorParser::new(tokens);
-    let selector = parser.parse().unwrap();
+    compile_or_panic(selector_string).entities(pr)
+}
 
-    let mut result = vec![];
+/// The fields matching any of the comma separated selectors, once each and in document order.
+/// Panics on an invalid selector, `Selector::compile_fields` reports it instead.
+pub fn select_field<'a>(root: &'a ParseResult, selector_string: &str) -> Vec<&'a AstFieldNode> {
+    compile_fields_or_panic(selector_string).fields(root)
+}
+
+/// The entities matching any of the comma separated selectors, once each and in document order.
+/// Panics on an invalid selector, `Selector::compile` reports it instead.
+pub fn select_entity_ids(pr: &ParseResult, selector_string: &str) -> Vec<EntityMatch> {
+    compile_or_panic(selector_string).entity_ids(pr)
+}
+
+/// The fields matching any of the comma separated selectors, once each and in document order.
+/// Panics on an invalid selector, `Selector::compile_fields` reports it instead.
+pub fn select_field_ids(root: &ParseResult, selector_string: &str) -> Vec<FieldMatch> {
+    compile_fields_or_panic(selector_string).field_ids(root)
+}
+
+fn compile_or_panic(selector_string: &str) -> CompiledSelector {
+    match Selector::compile(selector_string) {
+        Ok(compiled) => compiled,
+        Err(e) => panic!("Invalid selector {:?}: {}", selector_string, e),
+    }
+}
+
+fn compile_fields_or_panic(selector_string: &str) -> CompiledSelector {
+    match Selector::compile_fields(selector_string) {
+        Ok(compiled) => compiled,
+        Err(e) => panic!("Invalid field selector {:?}: {}", selector_string, e),
+    }
+}
+
+/// A parsed list of selectors, which can be run against any number of documents
+#[derive(Debug)]
+pub struct CompiledSelector {
+    selectors: Vec<Selector>,
+}
+
+impl Selector {
+    /// Parses a comma separated list of selectors, like `page > widget[kpi], tile:first-child`
+    pub fn compile(selector_string: &str) -> Result<CompiledSelector, SelectorError> {
+        let tokens = lex_selector(selector_string);
+        let parser = SelectorParser::new(tokens);
+        Ok(CompiledSelector { selectors: parser.parse_list()? })
+    }
+
+    /// Parses a comma separated list of selectors for fields, like `widget > .label, .value`. Predicates
+    /// and pseudo-classes only apply to entities, so the last compound of each selector, which names the
+    /// field, can not have them.
+    pub fn compile_fields(selector_string: &str) -> Result<CompiledSelector, SelectorError> {
+        let tokens = lex_selector(selector_string);
+        let parser = SelectorParser::new(tokens);
+        Ok(CompiledSelector { selectors: parser.parse_field_list()? })
+    }
+}
+
+impl CompiledSelector {
+    pub fn entities<'a>(&self, pr: &'a ParseResult) -> Vec<&'a AstEntityNode> {
+        self.entity_refs(pr, pr.index()).into_iter().map(|e| pr.get_entity(e)).collect()
+    }
 
-    for ent in &pr.entities {
-        if matches_selector(&ent, &selector) {
-            result.push(ent);
+    /// The matching fields. The last compound of each selector only gives the field name, use
+    /// `Selector::compile_fields` to reject selectors with more in it.
+    pub fn fields<'a>(&self, pr: &'a ParseResult) -> Vec<&'a AstFieldNode> {
+        self.field_refs(pr, pr.index()).into_iter().map(|f| pr.get_field(f)).collect()
+    }
+
+    /// The matching entities, once each and in document order
+    pub fn entity_ids(&self, pr: &ParseResult) -> Vec<EntityMatch> {
+        let index = pr.index();
+        self.entity_refs(pr, index).into_iter()
+            .map(|entity| EntityMatch { entity, path: index.path(entity) })
+            .collect()
+    }
+
+    /// The matching fields, once each and in document order
+    pub fn field_ids(&self, root: &ParseResult) -> Vec<FieldMatch> {
+        let index = root.index();
+        self.field_refs(root, index).into_iter()
+            .map(|field| {
+                let entity = index.field_entity(field).expect("selected fields are attached");
+                FieldMatch { field, entity, path: index.path(entity) }
+            })
+            .collect()
+    }
+
+    fn entity_refs(&self, pr: &ParseResult, index: &DocumentIndex) -> Vec<EntityRef> {
+        let mut selected = Vec::new();
+        for selector in &self.selectors {
+            selected.extend(select_entity_refs(selector, pr, index));
         }
+        // each selector finds entities in document order, but several can find the same one
+        if self.selectors.len() > 1 {
+            selected.sort_by_key(|e| index.pre_order(*e));
+            selected.dedup();
+        }
+        selected
     }
 
-    let mut current_selector = selector;
-    while current_selector.child.is_some() {
-        current_selector = *current_selector.child.unwrap();
-        let sub_results = select_in_entities(result, &current_selector, pr);
-        result = sub_results;
+    fn field_refs(&self, pr: &ParseResult, index: &DocumentIndex) -> Vec<EntityFieldRef> {
+        let mut selected = Vec::new();
+        for selector in &self.selectors {
+            selected.extend(select_field_refs(selector, pr, index));
+        }
+        if self.selectors.len() > 1 {
+            selected.sort_by_key(|f| field_order(*f, pr, index));
+            selected.dedup();
+        }
+        selected
     }
+}
 
-    return result;
+// where a field comes in the document, by its entity and then its place among the fields of the entity
+fn field_order(field: EntityFieldRef, pr: &ParseResult, index: &DocumentIndex) -> (usize, usize) {
+    let entity = index.field_entity(field).expect("selected fields are attached");
+    let position = pr.get_entity(entity).fields.iter().position(|f| *f == field);
+    (index.pre_order(entity), position.unwrap_or(0))
 }
 
-pub fn select_field<'a>(root: &'a ParseResult, selector_string: &str) -> Vec<&'a AstFieldNode> {
-    let tokens = lex_selector(selector_string);
-    let parser = SelectorParser::new(tokens);
-    let mut selector = parser.parse().unwrap();
+/// An expression found by `select_expr`, with the field and entity it is in and the path to that entity
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprMatch {
+    pub expr: EntityExprRef,
+    pub field: EntityFieldRef,
+    pub entity: EntityRef,
+    pub path: Vec<EntityRef>,
+}
 
-    let mut current_set = Vec::new();
-    for e in &root.entities {
-        current_set.push(e);
+impl ExprMatch {
+    pub fn node(&self) -> NodeRef {
+        NodeRef::Expr(self.expr)
     }
+}
 
-    // first pass , check in root entities
-    if selector.child.is_some() {
-        let mut next_set = Vec::new();
-        for e in current_set {
-            if matches_selector(e, &selector) {
-                next_set.push(e);
+/// The expressions matching any of the comma separated expression selectors, like `fn(COUNT)`,
+/// `vpath(survey:*)`, `ref(cr.*)` or `num(>1000)`. They are returned in document order, an operator or
+/// function before its operands. An invalid selector is an error.
+pub fn select_expr(pr: &ParseResult, selector_string: &str) -> Result<Vec<ExprMatch>, SelectorError> {
+    let selectors = parse_expr_selectors(selector_string)?;
+
+    let index = pr.index();
+    let mut result = Vec::new();
+    for entity in index.document_order() {
+        for field in &pr.get_entity(*entity).fields {
+            let mut stack = vec![pr.get_field(*field).value];
+            while let Some(expr_ref) = stack.pop() {
+                let expr = pr.get_expr(expr_ref);
+                if selectors.iter().any(|selector| matches_expr(expr, selector, pr)) {
+                    let path = index.path(*entity);
+                    result.push(ExprMatch { expr: expr_ref, field: *field, entity: *entity, path });
+                }
+                stack.extend(expr_children(expr).iter().rev());
             }
         }
-        current_set = next_set;
-        selector = *selector.child.unwrap();
     }
+    Ok(result)
+}
 
-    // pass 2 -> n , check in the current set
-    while selector.child.is_some() {
-        let next_set = select_in_entities(current_set, &selector, root);
-        current_set = next_set;
-        selector = *selector.child.unwrap();
+fn select_entity_refs(selector: &Selector, pr: &ParseResult, index: &DocumentIndex) -> Vec<EntityRef> {
+    let mut result = select_anywhere(selector, pr, index);
+
+    let mut current_selector = selector;
+    while let Some(ref child) = current_selector.child {
+        current_selector = child;
+        result = select_in_entities(&result, current_selector, pr, index);
     }
+    result
+}
 
+fn select_field_refs(selector: &Selector, pr: &ParseResult, index: &DocumentIndex) -> Vec<EntityFieldRef> {
+    let mut selector = selector;
+    let mut current_set = None;
 
-    // got to the last selector , should be a field selector
-    let mut result = Vec::new();
-    let mut fields = Vec::new();
-    for entity in current_set {
-        for field_ref in &entity.fields {
-            fields.push(root.get_field(*field_ref));
+    // first pass , check in all entities
+    if let Some(ref child) = selector.child {
+        let mut entities = select_anywhere(selector, pr, index);
+        selector = child;
+
+        // pass 2 -> n , check in the current set
+        while let Some(ref child) = selector.child {
+            entities = select_in_entities(&entities, selector, pr, index);
+            selector = child;
         }
+        current_set = Some(entities);
     }
-    for field in fields {
-        match selector.identifier {
-            Some(ref id) => {
-                if id == &field.identifier {
-                    result.push(field);
-                }
+
+    // got to the last selector , should be a field selector
+    let identifier = match selector.identifier {
+        Some(ref id) => id,
+        None => return Vec::new(),
+    };
+    let named = index.fields_named(identifier);
+    let entities = match current_set {
+        Some(entities) => entities,
+        None => return named.to_vec(),
+    };
+    match selector.combinator {
+        // `>` picks the fields of the current set, the other combinators those of its parents or siblings
+        Combinator::Child | Combinator::Parent | Combinator::NextSibling | Combinator::Sibling => {
+            let owners = if selector.combinator == Combinator::Child {
+                entities
+            } else {
+                related(&entities, selector.combinator, pr, index)
+            };
+            owners.iter()
+                .flat_map(|e| pr.get_entity(*e).fields.iter().cloned())
+                .filter(|f| &pr.get_field(*f).identifier == identifier)
+                .collect()
+        }
+        // fields of the current set, or of anything below it
+        Combinator::Descendant => {
+            let mut result = Vec::new();
+            for entity in outermost(&entities, index) {
+                let start = index.pre_order(entity);
+                let end = start + 1 + index.descendants(entity).len();
+                result.extend_from_slice(in_order_range(named, start, end, |f| {
+                    index.pre_order(index.field_entity(f).expect("indexed fields are attached"))
+                }));
             }
-            None => {}
+            result
         }
     }
+}
 
-    return result;
+// Entities anywhere in the document matching `selector`, in document order
+fn select_anywhere(selector: &Selector, pr: &ParseResult, index: &DocumentIndex) -> Vec<EntityRef> {
+    candidates(selector, index)
+        .unwrap_or_else(|| index.document_order())
+        .iter()
+        .cloned()
+        .filter(|e| matches_selector(*e, selector, pr, index))
+        .collect()
 }
 
-fn select_in_entities<'a>(entities: Vec<&AstEntityNode>, selector: &Selector, pr: &'a ParseResult) -> Vec<&'a AstEntityNode> {
+// Entities related to `entities` by the combinator of `selector` and matching it, like the ones below
+// them or their parents. `entities` must be in document order, and so is the result.
+fn select_in_entities(entities: &[EntityRef], selector: &Selector, pr: &ParseResult,
+                      index: &DocumentIndex) -> Vec<EntityRef> {
     let mut result = vec![];
-    for entity in entities {
-        for child_id in &entity.children {
-            let child = pr.get_entity(*child_id);
-            if matches_selector(child, selector) {
-                result.push(child);
+    match selector.combinator {
+        Combinator::Child | Combinator::Parent | Combinator::NextSibling | Combinator::Sibling => {
+            result = related(entities, selector.combinator, pr, index);
+            result.retain(|e| matches_selector(*e, selector, pr, index));
+        }
+        Combinator::Descendant => {
+            let candidates = candidates(selector, index);
+            for entity in outermost(entities, index) {
+                let below = match candidates {
+                    Some(candidates) => {
+                        let start = index.pre_order(entity) + 1;
+                        let end = start + index.descendants(entity).len();
+                        in_order_range(candidates, start, end, |e| index.pre_order(e))
+                    }
+                    None => index.descendants(entity),
+                };
+                result.extend(below.iter().cloned().filter(|e| matches_selector(*e, selector, pr, index)));
             }
-            let mut sub_results = select_in_entities(vec![child], selector, pr);
-            if sub_results.len() > 0 {
-                result.append(&mut sub_results);
+        }
+    }
+    result
+}
+
+// The children, parents or later siblings of `entities`, which are in document order. Each is returned
+// once, in document order.
+fn related(entities: &[EntityRef], combinator: Combinator, pr: &ParseResult,
+           index: &DocumentIndex) -> Vec<EntityRef> {
+    let mut result = Vec::new();
+    match combinator {
+        Combinator::Child => {
+            for entity in entities {
+                result.extend_from_slice(&pr.get_entity(*entity).children);
             }
         }
+        Combinator::Parent => result.extend(entities.iter().filter_map(|e| index.parent(*e))),
+        Combinator::NextSibling => {
+            for entity in entities {
+                result.extend(siblings(*entity, pr, index).get(index.sibling_position(*entity) + 1));
+            }
+        }
+        Combinator::Sibling => {
+            // the first entity found in a parent has every later sibling the others have
+            let mut parents = HashSet::new();
+            for entity in entities {
+                if parents.insert(index.parent(*entity)) {
+                    result.extend_from_slice(&siblings(*entity, pr, index)[index.sibling_position(*entity) + 1..]);
+                }
+            }
+        }
+        Combinator::Descendant => {
+            for entity in outermost(entities, index) {
+                result.extend_from_slice(index.descendants(entity));
+            }
+            return result;
+        }
     }
-    return result;
+    // the children of an entity come after the children of the entities below it, and the entities can
+    // share a parent
+    result.sort_by_key(|e| index.pre_order(*e));
+    result.dedup();
+    result
 }
 
+// The entities that can match `selector` going by its type, sub type and id, the shortest of the index
+// lists. `None` when the selector has none of them.
+fn candidates<'a>(selector: &Selector, index: &'a DocumentIndex) -> Option<&'a [EntityRef]> {
+    let lists = [
+        selector.main_type.as_ref().map(|t| index.entities_with_type(t)),
+        selector.sub_type.as_ref().map(|t| index.entities_with_sub_type(t)),
+        selector.identifier.as_ref().map(|i| index.entities_with_id(i)),
+    ];
+    lists.iter().filter_map(|l| *l).min_by_key(|l| l.len())
+}
 
-fn matches_selector(header: &AstEntityNode, selector: &Selector) -> bool {
+// `entities`, in document order, without the ones below another of them
+fn outermost(entities: &[EntityRef], index: &DocumentIndex) -> Vec<EntityRef> {
+    let mut result: Vec<EntityRef> = Vec::new();
+    for entity in entities {
+        // the entities kept do not overlap, so only the last one can have this one below it
+        match result.last() {
+            Some(last) if index.is_ancestor(*last, *entity) => {}
+            _ => result.push(*entity),
+        }
+    }
+    result
+}
+
+// The part of `nodes`, which are in document order, with a pre order position from `start` up to `end`
+fn in_order_range<F: Fn(usize) -> usize>(nodes: &[usize], start: usize, end: usize, pre_order: F) -> &[usize] {
+    let from = nodes.partition_point(|n| pre_order(*n) < start);
+    let to = nodes.partition_point(|n| pre_order(*n) < end);
+    &nodes[from..to]
+}
+
+// the entity and its siblings, in document order
+fn siblings<'a>(entity: EntityRef, pr: &'a ParseResult, index: &DocumentIndex) -> &'a [EntityRef] {
+    match index.parent(entity) {
+        Some(parent) => &pr.get_entity(parent).children,
+        None => &pr.root.children,
+    }
+}
+
+
+fn matches_selector(entity: EntityRef, selector: &Selector, pr: &ParseResult, index: &DocumentIndex) -> bool {
+    let header = pr.get_entity(entity);
     let matches = true;
     match selector.main_type {
         Some(ref s) => {
@@ -143,10 +435,70 @@ fn matches_selector(header: &AstEntityNode, selector: &Selector) -> bool {
         }
         None => {}
     }
+    if selector.reference.is_some() && header.reference != selector.reference {
+        // matching on a reference, but entity has another or none, no match
+        return false;
+    }
+
+    for pseudo_class in &selector.pseudo_classes {
+        let siblings = siblings(entity, pr, index);
+        let position = index.sibling_position(entity);
+        let matches_class = match *pseudo_class {
+            PseudoClass::Not(ref selectors) => !selectors.iter().any(|s| matches_selector(entity, s, pr, index)),
+            PseudoClass::FirstChild => position == 0,
+            PseudoClass::LastChild => position + 1 == siblings.len(),
+            PseudoClass::NthChild(n) => position + 1 == n,
+            PseudoClass::Empty => header.children.is_empty(),
+            PseudoClass::Root => index.parent(entity).is_none(),
+            PseudoClass::Has(ref selectors) => selectors.iter().any(|s| has_relative(entity, s, pr, index)),
+        };
+        if !matches_class {
+            return false;
+        }
+    }
+
+    for predicate in &selector.predicates {
+        let mut fields = header.fields.iter().map(|f| pr.get_field(*f)).filter(|f| f.identifier == predicate.field);
+        let found = match predicate.test {
+            Some(ref test) => fields.any(|field| matches_value(field, test, pr)),
+            None => fields.next().is_some(),
+        };
+        if !found {
+            return false;
+        }
+    }
 
     return matches;
 }
 
+// Tells if anything below `entity` matches `selector`, read as if `entity` came before it
+fn has_relative(entity: EntityRef, selector: &Selector, pr: &ParseResult, index: &DocumentIndex) -> bool {
+    let mut current_set = select_in_entities(&[entity], selector, pr, index);
+    let mut selector = selector;
+    while let Some(ref child) = selector.child {
+        if current_set.is_empty() {
+            break;
+        }
+        selector = child;
+        current_set = select_in_entities(&current_set, selector, pr, index);
+    }
+    !current_set.is_empty()
+}
+
+fn matches_value(field: &AstFieldNode, test: &ValueTest, pr: &ParseResult) -> bool {
+    let value = match *pr.get_expr(field.value) {
+        Expr::String(ref s) => s.value.to_string(),
+        ref expr => print_expr(expr, pr),
+    };
+    match *test {
+        ValueTest::Equals(ref s) => &value == s,
+        ValueTest::StartsWith(ref s) => value.starts_with(s.as_str()),
+        ValueTest::EndsWith(ref s) => value.ends_with(s.as_str()),
+        ValueTest::Contains(ref s) => value.contains(s.as_str()),
+        ValueTest::Matches(ref regex) => regex.is_match(&value),
+    }
+}
+
 
 #[cfg(test)]
 mod test {
@@ -154,6 +506,10 @@ mod test {
     use parse::Parser;
     use select::select_entity;
     use select::select_field;
+    use select::select_expr;
+    use select::{select_entity_ids, select_field_ids, EntityMatch, Selector};
+    use parse::NodeRef;
+    use print::print_expr;
 
     #[test]
     fn select_entity_simple() {
@@ -251,6 +607,49 @@ page {
         assert_eq!(select_entity(&root, "page > widget").len(), 4);
         assert_eq!(select_entity(&root, "page > widget[kpi]").len(), 2);
         assert_eq!(select_entity(&root, "page > widget[kpi2]").len(), 1);
+        assert_eq!(select_entity(&root, "page widget").len(), 4);
+        assert_eq!(select_entity(&root, "page widget[kpi]").len(), 2);
+        assert_eq!(select_entity(&root, "page widget[kpi2]").len(), 1);
+    }
+
+    #[test]
+    fn select_child_and_descendant() {
+        let cdl = "
+page {
+    widget kpi {
+        label : \"Label\"
+    }
+    group {
+        widget kpi {
+            label : \"Label\"
+        }
+        group {
+            widget kpi2 {
+                label : \"Label\"
+            }
+        }
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        assert_eq!(select_entity(&root, "page > widget").len(), 1);
+        assert_eq!(select_entity(&root, "page widget").len(), 3);
+        assert_eq!(select_entity(&root, "page > group > widget").len(), 1);
+        assert_eq!(select_entity(&root, "page group widget").len(), 2);
+        assert_eq!(select_entity(&root, "page group > widget[kpi2]").len(), 1);
+        assert_eq!(select_entity(&root, "page > widget[kpi2]").len(), 0);
+        // the inner group is below both groups, but is only found once
+        assert_eq!(select_entity(&root, "group group").len(), 1);
+        assert_eq!(select_entity(&root, "group widget").len(), 2);
+
+        assert_eq!(select_field(&root, "page > .label").len(), 0);
+        assert_eq!(select_field(&root, "page .label").len(), 3);
+        assert_eq!(select_field(&root, "page > group > .label").len(), 0);
+        assert_eq!(select_field(&root, "page > group .label").len(), 2);
     }
 
     #[test]
@@ -289,6 +688,423 @@ page {
         assert_eq!(select_field(&root, "page > widget[kpi3] > .label").len(), 1);
         assert_eq!(select_field(&root, "widget > .label").len(), 4);
     }
+
+    #[test]
+    fn select_with_field_predicates() {
+        let cdl = "
+page #overview {
+    widget kpi #accounts {
+        label : \"Accounts\"
+        size : large
+        value : COUNT(survey:responseid)
+    }
+    widget kpi #access {
+        label : 'Access'
+        size : small
+        value : average(score(survey:Q7)) * 100
+    }
+    widget table {
+        label : \"Health\"
+        column text #c1 {
+            format : formatterLTR
+        }
+        column text #c2 {
+            format : formatterRTL
+        }
+        column text #c3 {
+            format : formatterLTR
+            label : \"Account\"
+        }
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        let ids = |selector: &str| -> Vec<String> {
+            select_entity(&root, selector).iter().map(|e| e.identifier.clone().unwrap_or_default()).collect()
+        };
+        assert_eq!(ids("column{format=formatterLTR}"), vec!["c1", "c3"]);
+        assert_eq!(ids("column{format = 'formatterRTL'}"), vec!["c2"]);
+        assert_eq!(ids("widget{size}").len(), 2);
+        assert_eq!(ids("widget{size=large}"), vec!["accounts"]);
+        assert_eq!(ids("{label^=\"Acc\"}"), vec!["accounts", "access", "c3"]);
+        assert_eq!(ids("widget{label^=\"Acc\"}{size=small}"), vec!["access"]);
+        assert_eq!(ids("{label$=th}").len(), 1);
+        assert_eq!(ids("{label*=cce}"), vec!["access"]);
+        assert_eq!(ids("widget{value~='^COUNT\\('}"), vec!["accounts"]);
+        assert_eq!(ids("widget{value~='\\* 100$'}"), vec!["access"]);
+        assert_eq!(ids("widget[table] > column{label}"), vec!["c3"]);
+        assert_eq!(ids("widget{format}").len(), 0);
+
+        assert_eq!(select_field(&root, "widget{size=large} > .label").len(), 1);
+        assert_eq!(ids("widget{size=-1}").len(), 0);
+    }
+
+    #[test]
+    fn select_lists_negation_and_wildcard() {
+        let cdl = "
+page #p1 {
+    tile kpi #t1 {
+        label : \"T1\"
+    }
+    widget table #w1 {
+        column metric #c1 {
+            label : \"C1\"
+        }
+        column text #c2 {
+            label : \"C2\"
+        }
+    }
+    tile chart #t2 {
+        label : \"T2\"
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        let ids = |selector: &str| -> Vec<String> {
+            select_entity(&root, selector).iter().map(|e| e.identifier.clone().unwrap_or_default()).collect()
+        };
+        // document order, whatever the order of the selectors
+        assert_eq!(ids("column, tile"), vec!["t1", "c1", "c2", "t2"]);
+        assert_eq!(ids("tile, column:not([metric])"), vec!["t1", "c2", "t2"]);
+        // matched by both, returned once
+        assert_eq!(ids("tile, [kpi], page tile"), vec!["t1", "t2"]);
+        assert_eq!(ids("*").len(), 6);
+        assert_eq!(ids("page > *"), vec!["t1", "w1", "t2"]);
+        assert_eq!(ids("page > *:not(tile)"), vec!["w1"]);
+        assert_eq!(ids("*:not(page, widget, .t1, [metric])"), vec!["c2", "t2"]);
+        assert_eq!(ids("page * *"), vec!["c1", "c2"]);
+
+        let labels: Vec<String> = select_field(&root, "column > .label, tile[chart] > .label, column .label").iter()
+            .map(|f| print_expr(root.get_expr(f.value), &root))
+            .collect();
+        assert_eq!(labels, vec!["\"C1\"", "\"C2\"", "\"T2\""]);
+    }
+
+    #[test]
+    fn select_structural_pseudo_classes() {
+        let cdl = "
+page #p1 {
+    widget accountList #l1 {
+        label : \"Accounts\"
+        column text #c1 {
+        }
+        column text #c2 {
+        }
+        column text #c3 {
+        }
+    }
+}
+page #p2 {
+    widget kpi #k1 {
+        value : 1
+    }
+    widget accountList #l2 {
+        column text #c4 {
+        }
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        let ids = |selector: &str| -> Vec<String> {
+            select_entity(&root, selector).iter()
+                .map(|e| e.identifier.clone().or_else(|| e.sub_type.clone()).unwrap_or_default())
+                .collect()
+        };
+        assert_eq!(ids("widget[accountList] > column:first-child"), vec!["c1", "c4"]);
+        assert_eq!(ids("column:last-child"), vec!["c3", "c4"]);
+        assert_eq!(ids("column:first-child:last-child"), vec!["c4"]);
+        assert_eq!(ids("column:nth-child(2)"), vec!["c2"]);
+        assert_eq!(ids("widget:nth-child(2)"), vec!["l2"]);
+        assert_eq!(ids(":root"), vec!["p1", "p2"]);
+        assert_eq!(ids("page:first-child"), vec!["p1"]);
+        assert_eq!(ids("widget:empty"), vec!["k1"]);
+        assert_eq!(ids("*:not(:empty)"), vec!["p1", "l1", "p2", "l2"]);
+        // pages that have a widget with no label
+        assert_eq!(ids("page:has(widget:not({label}))"), vec!["p2"]);
+        assert_eq!(ids("page:has(> widget > column:nth-child(3))"), vec!["p1"]);
+        assert_eq!(ids("page:has(> column)").len(), 0);
+        assert_eq!(ids("page:has(column)"), vec!["p1", "p2"]);
+        assert_eq!(ids("*:has(column, [kpi])"), vec!["p1", "l1", "p2", "l2"]);
+    }
+
+    #[test]
+    fn select_ids_and_references() {
+        let cdl = "
+page #overview {
+    widget kpi #kpi1 @default {
+        label : \"KPI 1\"
+    }
+    widget kpi #kpi2 @cr.base {
+        label : \"KPI 2\"
+    }
+    widget kpi #kpi3 {
+        label : \"KPI 3\"
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        let ids = |selector: &str| -> Vec<String> {
+            select_entity(&root, selector).iter().map(|e| e.identifier.clone().unwrap_or_default()).collect()
+        };
+        assert_eq!(ids("widget#kpi1"), vec!["kpi1"]);
+        assert_eq!(ids("widget.kpi1"), vec!["kpi1"]);
+        assert_eq!(ids("#kpi2, #kpi3"), vec!["kpi2", "kpi3"]);
+        assert_eq!(ids("widget@default"), vec!["kpi1"]);
+        assert_eq!(ids("[@default]"), vec!["kpi1"]);
+        assert_eq!(ids("widget[kpi]@cr.base"), vec!["kpi2"]);
+        assert_eq!(ids("widget:not([@default])"), vec!["kpi2", "kpi3"]);
+        assert_eq!(ids("widget#kpi1@cr.base").len(), 0);
+        assert_eq!(select_entity(&root, "page#overview > [@default]").len(), 1);
+        assert_eq!(select_field(&root, "#kpi3 > .label").len(), 1);
+    }
+
+    #[test]
+    fn select_expressions() {
+        let cdl = "
+page #overview {
+    widget kpi #kpi1 {
+        label : \"Responses\"
+        value : COUNT(survey:responseid) + count(hub:id)
+        target : 1500
+    }
+    widget kpi #kpi2 {
+        value : average(score(survey:Q7), @cr.currentPeriodB2b) * 100
+        benchmark : @cr.benchmark
+        target : 1000
+        size : large
+    }
 }
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
 
+        let found = |selector: &str| -> Vec<String> {
+            select_expr(&root, selector).unwrap().iter()
+                .map(|m| format!("{}.{}: {}", root.get_entity(m.entity).identifier.as_ref().unwrap(),
+                                 root.get_field(m.field).identifier, print_expr(root.get_expr(m.expr), &root)))
+                .collect()
+        };
+        assert_eq!(found("fn(COUNT)"), vec![
+            "kpi1.value: COUNT(survey:responseid)",
+            "kpi1.value: count(hub:id)",
+        ]);
+        assert_eq!(found("vpath(survey:*)"), vec!["kpi1.value: survey:responseid", "kpi2.value: survey:Q7"]);
+        assert_eq!(found("ref(cr.*)"), vec!["kpi2.value: @cr.currentPeriodB2b", "kpi2.benchmark: @cr.benchmark"]);
+        assert_eq!(found("num(>1000)"), vec!["kpi1.target: 1500"]);
+        assert_eq!(found("num(>=1000)").len(), 2);
+        // outer expressions before the ones inside them
+        assert_eq!(found("fn(*), vpath(*:Q7)"), vec![
+            "kpi1.value: COUNT(survey:responseid)",
+            "kpi1.value: count(hub:id)",
+            "kpi2.value: average(score(survey:Q7), @cr.currentPeriodB2b)",
+            "kpi2.value: score(survey:Q7)",
+            "kpi2.value: survey:Q7",
+        ]);
+        assert_eq!(found("str(Resp*), id(large)"), vec!["kpi1.label: \"Responses\"", "kpi2.size: large"]);
+        assert_eq!(found("fn(SUM)").len(), 0);
 
+        let kpi2 = root.index().entities_with_id("kpi2")[0];
+        let matches = select_expr(&root, "num(1000)").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].entity, matches[0].path.len()), (kpi2, 1));
+        assert_eq!(root.get_field(matches[0].field).value, matches[0].expr);
+        assert_eq!(select_expr(&root, "fn(").unwrap_err().to_string(), "Missing ')' after fn( at position 3");
+    }
+
+    #[test]
+    fn select_ids_with_paths() {
+        let cdl = "
+page #p1 {
+    widget kpi #k1 {
+        label : \"K1\"
+        group {
+            widget kpi #k2 {
+                label : \"K2\"
+            }
+        }
+    }
+}
+page #p2 {
+    widget kpi #k3 {
+        label : \"K3\"
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        let root = parser.parse().unwrap();
+
+        let name = |entity: usize| {
+            let node = root.get_entity(entity);
+            node.identifier.clone().or_else(|| node.sub_type.clone()).unwrap_or_else(|| node.main_type.clone())
+        };
+        let matches = select_entity_ids(&root, "page widget, widget widget");
+        let found: Vec<(String, Vec<String>)> = matches.iter()
+            .map(|m| (name(m.entity), m.path.iter().map(|e| name(*e)).collect()))
+            .collect();
+        assert_eq!(found, vec![
+            ("k1".to_string(), vec!["p1".to_string()]),
+            ("k2".to_string(), vec!["p1".to_string(), "k1".to_string(), "group".to_string()]),
+            ("k3".to_string(), vec!["p2".to_string()]),
+        ]);
+        assert_eq!(matches[0].node(), NodeRef::Entity(matches[0].entity));
+        assert_eq!(select_entity_ids(&root, ":root")[0], EntityMatch { entity: matches[0].path[0], path: vec![] });
+
+        let fields = select_field_ids(&root, "page .label, widget > .label");
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[1].entity, matches[1].entity);
+        assert_eq!(fields[1].path, matches[1].path);
+        assert_eq!(root.get_field(fields[1].field).identifier, "label");
+        assert_eq!(fields[2].node(), NodeRef::Field(fields[2].field));
+
+        // the ids can be used to change the document
+        let mut root = root;
+        root.remove_entity(matches[1].entity).unwrap();
+        assert_eq!(select_entity_ids(&root, "widget").len(), 2);
+    }
+
+    #[test]
+    fn select_in_nested_entities() {
+        let cdl = "
+group box #g1 {
+    group box #g2 {
+        label : \"G2\"
+        group box #g3 {
+            tile box #t1 {
+                label : \"T1\"
+            }
+        }
+    }
+    tile box #t2 {
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let root = Parser::new(lexer.lex().unwrap()).parse().unwrap();
+        let ids = |selector: &str| -> Vec<String> {
+            select_entity(&root, selector).iter().map(|e| e.identifier.clone().unwrap()).collect()
+        };
+
+        // an entity below several matches is found once, in document order
+        assert_eq!(ids("group tile"), vec!["t1", "t2"]);
+        assert_eq!(ids("group group"), vec!["g2", "g3"]);
+        assert_eq!(ids("group > group > group tile"), vec!["t1"]);
+        assert_eq!(ids("group > tile, group group"), vec!["g2", "g3", "t1", "t2"]);
+        assert_eq!(ids("#g2 *"), vec!["g3", "t1"]);
+        assert_eq!(select_field(&root, "group .label").len(), 2);
+        assert_eq!(select_field(&root, "#g3 > .label").len(), 0);
+        assert_eq!(select_field(&root, "#g3 .label").len(), 1);
+    }
+
+    #[test]
+    fn select_parents_and_siblings() {
+        let cdl = "
+config report #cr {
+    formatter number #f1 {
+        numberDecimals : 1
+    }
+    formatter date #f2 {
+    }
+    formatter number #f3 {
+        numberDecimals : 0
+    }
+}
+page overview #p1 {
+    widget table #w1 {
+        label : \"W1\"
+        column text #c1 {
+        }
+        column text #c2 {
+        }
+    }
+    widget kpi #w2 {
+        tile value #t1 {
+        }
+    }
+}
+".to_string();
+        let lexer = Lexer::new(cdl);
+        let root = Parser::new(lexer.lex().unwrap()).parse().unwrap();
+        let ids = |selector: &str| -> Vec<String> {
+            select_entity(&root, selector).iter().map(|e| e.identifier.clone().unwrap()).collect()
+        };
+
+        // the widget containing a column, once for both columns
+        assert_eq!(ids("column < widget"), vec!["w1"]);
+        assert_eq!(ids("column#c2 < widget < page"), vec!["p1"]);
+        assert_eq!(ids("tile < widget[table]").len(), 0);
+        assert_eq!(ids("formatter#f1 ~ formatter"), vec!["f2", "f3"]);
+        assert_eq!(ids("formatter#f1 + formatter"), vec!["f2"]);
+        assert_eq!(ids("formatter + formatter"), vec!["f2", "f3"]);
+        assert_eq!(ids("formatter[number] ~ formatter[number]"), vec!["f3"]);
+        assert_eq!(ids("column < widget + widget > tile"), vec!["t1"]);
+        assert_eq!(ids("widget:has(+ widget)"), vec!["w1"]);
+        assert_eq!(ids("column:has(< widget[table])"), vec!["c1", "c2"]);
+
+        assert_eq!(select_field(&root, "column < .label").len(), 1);
+        assert_eq!(select_field(&root, "formatter#f2 ~ .numberDecimals").len(), 1);
+        assert_eq!(select_field(&root, "formatter#f2 + .numberDecimals").len(), 1);
+        assert_eq!(select_field(&root, "formatter#f1 + .numberDecimals").len(), 0);
+
+        let error = Selector::compile("column < ~ widget").unwrap_err();
+        assert_eq!((error.message.as_str(), error.position), ("Expected a selector, got '~'", 9));
+    }
+
+    #[test]
+    fn compiled_selectors() {
+        let compiled = Selector::compile("page > widget[kpi], .label").unwrap();
+        for cdl in &["page {\n    widget kpi {\n        label : \"K\"\n    }\n}\n",
+                     "page {\n    widget kpi {\n    }\n    widget kpi {\n    }\n}\n"] {
+            let lexer = Lexer::new(cdl.to_string());
+            let root = Parser::new(lexer.lex().unwrap()).parse().unwrap();
+            assert_eq!(compiled.entities(&root).len(), select_entity(&root, "page > widget[kpi], .label").len());
+            assert_eq!(compiled.fields(&root).len(), select_field(&root, "page > widget[kpi], .label").len());
+        }
+
+        let error = |selector: &str| {
+            let e = Selector::compile(selector).unwrap_err();
+            (e.message, e.position)
+        };
+        assert_eq!(error("widget!"), ("Unexpected '!'".to_string(), 6));
+        assert_eq!(error("widget[kpi"), ("Expected ']', got the end".to_string(), 10));
+        assert_eq!(error("page >"), ("Expected a selector, got the end".to_string(), 6));
+        assert_eq!(error("tile, , column"), ("Expected a selector, got ','".to_string(), 6));
+        assert_eq!(error(""), ("Expected a selector, got the end".to_string(), 0));
+        assert_eq!(error("tile:nth-child(x)"), ("Expected a number from 1 in :nth-child(), got 'x'".to_string(), 15));
+        assert_eq!(error("tile:first"), ("Unknown pseudo-class :first".to_string(), 5));
+        assert_eq!(error("tile{label=\"x}"), ("Expected a value, got an unterminated string".to_string(), 11));
+        assert_eq!(error("tile{label~='('}").1, 12);
+        assert_eq!(Selector::compile("page >> tile").unwrap_err().to_string(),
+                   "Expected a selector, got '>' at position 6");
+
+        let field_error = |selector: &str| {
+            let e = Selector::compile_fields(selector).unwrap_err();
+            (e.message, e.position)
+        };
+        let message = "Expected a field name without predicates or pseudo-classes".to_string();
+        assert_eq!(field_error("widget .label:first-child"), (message.clone(), 13));
+        assert_eq!(field_error("tile, widget > .label{x}"), (message.clone(), 21));
+        assert_eq!(field_error("widget:has(tile) > .label:not(.x)"), (message, 25));
+        assert!(Selector::compile_fields("widget:has(tile){size} > .label, .value").is_ok());
+        assert!(Selector::compile("widget .label:first-child").is_ok());
+    }
+}
@@ -1,39 +1,138 @@
 use std::cell::RefCell;
 use std::cell::Cell;
 use select::lex::LexItem;
+use lex::Span;
 use std::cell::Ref;
+use std::error::Error;
+use std::fmt;
+use regex::Regex;
 
 #[derive(Debug)]
 pub struct Selector {
     pub main_type: Option<String>,
     pub sub_type: Option<String>,
+    /// `.id` or `#id`
     pub identifier: Option<String>,
+    /// `@ref` or `[@ref]`, the entity the matching ones are based on
+    pub reference: Option<String>,
+    pub predicates: Vec<FieldPredicate>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    /// how this selector relates to the one it is the child of
+    pub combinator: Combinator,
     pub child: Option<Box<Selector>>,
 }
 
+/// `{field}`, the entity has the field, or `{field<op>value}`, one of its fields with that name has a
+/// matching value
+#[derive(Debug)]
+pub struct FieldPredicate {
+    pub field: String,
+    pub test: Option<ValueTest>,
+}
+
+/// Values are compared as they are printed, strings without their quotes
+#[derive(Debug)]
+pub enum ValueTest {
+    /// `=`
+    Equals(String),
+    /// `^=`
+    StartsWith(String),
+    /// `$=`
+    EndsWith(String),
+    /// `*=`
+    Contains(String),
+    /// `~=`, a regular expression found anywhere in the value
+    Matches(Regex),
+}
+
+#[derive(Debug)]
+pub enum PseudoClass {
+    /// `:not(a, b)`, matches none of the selectors
+    Not(Vec<Selector>),
+    /// `:first-child`
+    FirstChild,
+    /// `:last-child`
+    LastChild,
+    /// `:nth-child(n)`, counting from 1
+    NthChild(usize),
+    /// `:empty`, no child entities
+    Empty,
+    /// `:root`, a top level entity
+    Root,
+    /// `:has(a, > b, < c)`, something related to the entity by the leading combinator, anywhere below
+    /// when there is none, matches one of the selectors
+    Has(Vec<Selector>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combinator {
+    /// `a b`, anywhere below
+    Descendant,
+    /// `a > b`, directly below
+    Child,
+    /// `a < b`, directly above, the parent
+    Parent,
+    /// `a + b`, the next sibling
+    NextSibling,
+    /// `a ~ b`, any later sibling
+    Sibling,
+}
+
+impl Combinator {
+    // the combinator a token stands for, the descendant one is whitespace
+    fn from_token(token: &LexItem) -> Option<Combinator> {
+        match *token {
+            LexItem::Arrow => Some(Combinator::Child),
+            LexItem::LeftArrow => Some(Combinator::Parent),
+            LexItem::Plus => Some(Combinator::NextSibling),
+            LexItem::Tilde => Some(Combinator::Sibling),
+            LexItem::Space => Some(Combinator::Descendant),
+            _ => None,
+        }
+    }
+}
+
+/// A selector that could not be parsed, `position` is the byte offset in the selector of the problem
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for SelectorError {}
+
 #[derive(Debug)]
 pub struct SelectorParser {
-    tokens: RefCell<Vec<LexItem>>,
+    tokens: RefCell<Vec<(LexItem, Span)>>,
     index: Cell<usize>,
+    end: usize,
+    // where the predicates and pseudo-classes of the last compound parsed start
+    compound_extras: Cell<Option<usize>>,
 }
 
 impl SelectorParser {
-    pub fn new(tokens: Vec<LexItem>) -> SelectorParser {
+    pub fn new(tokens: Vec<(LexItem, Span)>) -> SelectorParser {
+        // errors at the end of the selector are reported after the last token
+        let end = tokens.last().map_or(0, |&(_, span)| span.end);
         SelectorParser {
             tokens: RefCell::from(tokens),
             index: Cell::from(0),
+            end,
+            compound_extras: Cell::from(None),
         }
     }
 
     fn peek_current_token(&self) -> Ref<LexItem> {
-        Ref::map(self.tokens.borrow(), |tokens| &tokens[self.index.get()])
+        Ref::map(self.tokens.borrow(), |tokens| &tokens[self.index.get()].0)
     }
 
 
-    fn get_current_token(&self) -> Ref<LexItem> {
-        self.advance_stream();
-        Ref::map(self.tokens.borrow(), |tokens| &tokens[self.index.get() - 1])
-    }
 
     fn advance_stream(&self) {
         if self.index.get() + 1 <= self.tokens.borrow().len() {
@@ -47,83 +146,309 @@ impl SelectorParser {
         self.index.get() < self.tokens.borrow().len()
     }
 
-    fn eat_token_if(&self, token: LexItem) {
-        if *self.peek_current_token() == token {
+    fn next_is(&self, token: &LexItem) -> bool {
+        self.has_items() && *self.peek_current_token() == *token
+    }
+
+    fn eat_token_if(&self, token: LexItem) -> Result<(), SelectorError> {
+        if self.next_is(&token) {
             self.advance_stream();
+            Ok(())
         } else {
-            panic!("Trying to advance the token stream, but got unexpected token.\n\
-                    Got {:?} expexted {:?} ", self.peek_current_token(), token);
+            self.error(format!("Expected {}, got {}", token.describe(), self.describe_current()))
         }
     }
 
+    // where the current token starts, or the end of the selector
+    fn position(&self) -> usize {
+        self.tokens.borrow().get(self.index.get()).map_or(self.end, |&(_, span)| span.start)
+    }
+
+    fn describe_current(&self) -> String {
+        if self.has_items() { self.peek_current_token().describe() } else { "the end".to_string() }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, SelectorError> {
+        Err(SelectorError { message, position: self.position() })
+    }
+
+    fn expect_identifier(&self, after: &str) -> Result<String, SelectorError> {
+        if self.has_items() {
+            if let LexItem::Identifier(ref s) = *self.peek_current_token() {
+                let ident = s.to_string();
+                self.advance_stream();
+                return Ok(ident);
+            }
+        }
+        self.error(format!("Expected a name after {}, got {}", after, self.describe_current()))
+    }
+
+
+    pub fn parse(&self) -> Result<Selector, SelectorError> {
+        self.parse_selector(Combinator::Descendant)
+    }
+
+    /// Parses a comma separated list of selectors, like `tile, column > .label`
+    pub fn parse_list(&self) -> Result<Vec<Selector>, SelectorError> {
+        let mut selectors = vec![self.parse()?];
+        while self.has_items() {
+            self.eat_token_if(LexItem::Comma)?;
+            selectors.push(self.parse_selector(Combinator::Descendant)?);
+        }
+        Ok(selectors)
+    }
 
-    pub fn parse(&self) -> Result<Selector, String> {
-        return Ok(self.parse_selector()?);
+    /// Parses a comma separated list of selectors for fields, like `.label, widget > .value`. The field
+    /// is named by the last compound, which can not have predicates or pseudo-classes.
+    pub fn parse_field_list(&self) -> Result<Vec<Selector>, SelectorError> {
+        let mut selectors = Vec::new();
+        loop {
+            if !selectors.is_empty() {
+                self.eat_token_if(LexItem::Comma)?;
+            }
+            selectors.push(self.parse_selector(Combinator::Descendant)?);
+            if let Some(position) = self.compound_extras.get() {
+                return Err(SelectorError {
+                    message: "Expected a field name without predicates or pseudo-classes".to_string(),
+                    position,
+                });
+            }
+            if !self.has_items() {
+                return Ok(selectors);
+            }
+        }
     }
 
 
-    fn parse_selector(&self) -> Result<Selector,String> {
+    fn parse_selector(&self, combinator: Combinator) -> Result<Selector, SelectorError> {
+        let mut res = self.parse_compound(combinator)?;
+        if self.has_items() && !self.next_is(&LexItem::Comma) && !self.next_is(&LexItem::ClosePar) {
+            let combinator = match Combinator::from_token(&self.peek_current_token()) {
+                Some(combinator) => combinator,
+                None => return self.error(format!("Unexpected {}", self.describe_current())),
+            };
+            self.advance_stream();
+            res.child = Some(Box::new(self.parse_selector(combinator)?));
+        }
+        Ok(res)
+    }
+
+    // one selector without combinators, like `*`, `widget[kpi].id{size}` or `column:not([metric])`
+    fn parse_compound(&self, combinator: Combinator) -> Result<Selector, SelectorError> {
+        let start = self.index.get();
         let mut res = Selector {
             main_type: None,
             sub_type: None,
             identifier: None,
+            reference: None,
+            predicates: Vec::new(),
+            pseudo_classes: Vec::new(),
+            combinator,
             child: None,
         };
-        if self.has_items() {
+        if self.next_is(&LexItem::Star) {
+            // the universal type, the same as leaving the type out
+            self.advance_stream();
+        } else if self.has_items() {
+            let main_type = match *self.peek_current_token() {
+                LexItem::Identifier(ref s) => Some(s.to_string()),
+                _ => None,
+            };
+            if main_type.is_some() {
+                self.advance_stream();
+                res.main_type = main_type;
+            }
+        }
+        // the other parts can come in any order, like `[kpi]#kpi1@default{label}:first-child`
+        let mut extras = None;
+        while self.has_items() {
             match *self.peek_current_token() {
-                LexItem::Identifier(ref s) => {
+                LexItem::OpenBrace | LexItem::Colon if extras.is_none() => extras = Some(self.position()),
+                _ => {}
+            }
+            match *self.peek_current_token() {
+                LexItem::OpenSquare => {
                     self.advance_stream();
-                    res.main_type = Some(s.to_string());
+                    match *self.peek_current_token_or_error()? {
+                        LexItem::Identifier(ref s) => res.sub_type = Some(s.to_string()),
+                        LexItem::Reference(ref s) => res.reference = Some(s.to_string()),
+                        ref token => {
+                            return self.error(format!("Expected a sub type or reference in [], got {}",
+                                                      token.describe()));
+                        }
+                    };
+                    self.advance_stream();
+                    self.eat_token_if(LexItem::CloseSquare)?;
                 }
-                LexItem::Arrow => {
+                LexItem::Dot => {
                     self.advance_stream();
-                    res.child = Some(Box::new( self.parse_selector()?));
+                    res.identifier = Some(self.expect_identifier("'.'")?);
                 }
-                _ => {}
+                LexItem::Hash => {
+                    self.advance_stream();
+                    res.identifier = Some(self.expect_identifier("'#'")?);
+                }
+                LexItem::Reference(ref s) => {
+                    self.advance_stream();
+                    res.reference = Some(s.to_string());
+                }
+                LexItem::OpenBrace => res.predicates.push(self.parse_predicate()?),
+                LexItem::Colon => res.pseudo_classes.push(self.parse_pseudo_class()?),
+                _ => break,
             }
         }
-        if self.has_items() {
-            match *self.peek_current_token() {
-                LexItem::OpenSquare => {
-                    self.eat_token_if(LexItem::OpenSquare);
-                    let ident = match *self.get_current_token() {
-                        LexItem::Identifier(ref s) => { s.to_string() }
-                        _ => panic!("didnt find identifier inside square brackets")
-                    };
-                    self.eat_token_if(LexItem::CloseSquare);
-                    res.sub_type = Some(ident);
+        if self.index.get() == start {
+            return self.error(format!("Expected a selector, got {}", self.describe_current()));
+        }
+        self.compound_extras.set(extras);
+        Ok(res)
+    }
+
+    fn parse_pseudo_class(&self) -> Result<PseudoClass, SelectorError> {
+        self.eat_token_if(LexItem::Colon)?;
+        let position = self.position();
+        let name = self.expect_identifier("':'")?;
+        match name.as_str() {
+            "not" => {
+                self.eat_token_if(LexItem::OpenPar)?;
+                let mut selectors = vec![self.parse_compound(Combinator::Descendant)?];
+                while self.next_is(&LexItem::Comma) {
+                    self.advance_stream();
+                    selectors.push(self.parse_compound(Combinator::Descendant)?);
+                }
+                if !self.next_is(&LexItem::ClosePar) {
+                    return self.error(format!("Expected ')' closing :not(), got {}, only selectors without \
+                                               combinators can be negated", self.describe_current()));
                 }
-                LexItem::Arrow => {
+                self.advance_stream();
+                Ok(PseudoClass::Not(selectors))
+            }
+            "has" => {
+                self.eat_token_if(LexItem::OpenPar)?;
+                let mut selectors = vec![self.parse_relative()?];
+                while self.next_is(&LexItem::Comma) {
                     self.advance_stream();
-                    res.child = Some(Box::new( self.parse_selector()?));
+                    selectors.push(self.parse_relative()?);
                 }
-                _ => {}
+                self.eat_token_if(LexItem::ClosePar)?;
+                Ok(PseudoClass::Has(selectors))
             }
+            "nth-child" => {
+                self.eat_token_if(LexItem::OpenPar)?;
+                let n = match *self.peek_current_token_or_error()? {
+                    LexItem::Identifier(ref s) => s.parse::<usize>().ok().filter(|n| *n > 0),
+                    _ => None,
+                };
+                match n {
+                    Some(n) => {
+                        self.advance_stream();
+                        self.eat_token_if(LexItem::ClosePar)?;
+                        Ok(PseudoClass::NthChild(n))
+                    }
+                    None => self.error(format!("Expected a number from 1 in :nth-child(), got {}",
+                                               self.describe_current())),
+                }
+            }
+            "first-child" => Ok(PseudoClass::FirstChild),
+            "last-child" => Ok(PseudoClass::LastChild),
+            "empty" => Ok(PseudoClass::Empty),
+            "root" => Ok(PseudoClass::Root),
+            _ => Err(SelectorError { message: format!("Unknown pseudo-class :{}", name), position }),
         }
+    }
+
+    fn peek_current_token_or_error(&self) -> Result<Ref<'_, LexItem>, SelectorError> {
         if self.has_items() {
-            match *self.peek_current_token() {
-                LexItem::Dot => {
-                    self.eat_token_if(LexItem::Dot);
-                    let ident = match *self.get_current_token() {
-                        LexItem::Identifier(ref s) => { s.to_string() }
-                        _ => panic!("didnt find identifier after dot")
-                    };
-                    res.identifier = Some(ident);
+            Ok(self.peek_current_token())
+        } else {
+            self.error("Unexpected end of selector".to_string())
+        }
+    }
+
+    // a selector inside :has(), starting with a combinator like `>` when it is not about the descendants
+    fn parse_relative(&self) -> Result<Selector, SelectorError> {
+        let combinator = if self.has_items() { Combinator::from_token(&self.peek_current_token()) } else { None };
+        match combinator {
+            Some(combinator) => {
+                self.advance_stream();
+                self.parse_selector(combinator)
+            }
+            None => self.parse_selector(Combinator::Descendant),
+        }
+    }
+
+    fn parse_predicate(&self) -> Result<FieldPredicate, SelectorError> {
+        self.eat_token_if(LexItem::OpenBrace)?;
+        let field = self.expect_identifier("'{'")?;
+        let operator = match *self.peek_current_token_or_error()? {
+            LexItem::CloseBrace => None,
+            LexItem::Equal => Some('='),
+            LexItem::Caret => Some('^'),
+            LexItem::Dollar => Some('$'),
+            LexItem::Star => Some('*'),
+            LexItem::Tilde => Some('~'),
+            ref token => {
+                return self.error(format!("Expected an operator or '}}' after {}, got {}", field, token.describe()));
+            }
+        };
+        self.advance_stream();
+        let operator = match operator {
+            Some(operator) => operator,
+            None => return Ok(FieldPredicate { field, test: None }),
+        };
+        if operator != '=' {
+            self.eat_token_if(LexItem::Equal)?;
+        }
+        let position = self.position();
+        let value = self.parse_value()?;
+        self.eat_token_if(LexItem::CloseBrace)?;
+        let test = match operator {
+            '=' => ValueTest::Equals(value),
+            '^' => ValueTest::StartsWith(value),
+            '$' => ValueTest::EndsWith(value),
+            '*' => ValueTest::Contains(value),
+            _ => match Regex::new(&value) {
+                Ok(regex) => ValueTest::Matches(regex),
+                Err(e) => {
+                    return Err(SelectorError { message: format!("Invalid regex for {}: {}", field, e), position });
                 }
-                LexItem::Arrow => {
-                    self.advance_stream();
-                    res.child = Some(Box::new( self.parse_selector()?));
+            },
+        };
+        Ok(FieldPredicate { field, test: Some(test) })
+    }
+
+    // a quoted string, or unquoted text like `formatterLTR`, `0.5` or `-1`. Spaces are only kept in quoted
+    // strings, so unquoted text with spaces is an error.
+    fn parse_value(&self) -> Result<String, SelectorError> {
+        if let LexItem::String(ref s) = *self.peek_current_token_or_error()? {
+            let value = s.to_string();
+            self.advance_stream();
+            return Ok(value);
+        }
+        let mut value = String::new();
+        while self.has_items() {
+            match *self.peek_current_token() {
+                // two names in a row were separated by a space
+                LexItem::Identifier(_) if value.ends_with(|c: char| c.is_alphanumeric() || c == '_') => {
+                    return self.error(format!("Expected '}}' after {}, quote values with spaces", value));
                 }
-                _ => {}
+                LexItem::Identifier(ref s) => value.push_str(s),
+                LexItem::Dot => value.push('.'),
+                LexItem::Hash => value.push('#'),
+                _ => break,
             }
+            self.advance_stream();
         }
-        Ok(res)
+        if value.is_empty() {
+            return self.error(format!("Expected a value, got {}", self.describe_current()));
+        }
+        Ok(value)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use select::parse::SelectorParser;
+    use select::parse::{Combinator, PseudoClass, SelectorParser, ValueTest};
     use select::lex::lex_selector;
 
     #[test]
@@ -208,8 +533,164 @@ mod test {
         assert_eq!(sel.identifier.is_none(), true);
         assert_eq!(sel.child.is_some(), true);
         let child = sel.child.unwrap();
+        assert_eq!(child.combinator, Combinator::Child);
         assert_eq!(child.identifier.unwrap(), "label");
     }
-}
 
+    #[test]
+    fn parse_combinators() {
+        let s = "page widget[kpi] > .label";
+        let tokens = lex_selector(s);
+        let parser = SelectorParser::new(tokens);
+        let sel = parser.parse().unwrap();
+        assert_eq!(sel.main_type.unwrap(), "page");
+        let widget = sel.child.unwrap();
+        assert_eq!(widget.combinator, Combinator::Descendant);
+        assert_eq!(widget.main_type.unwrap(), "widget");
+        assert_eq!(widget.sub_type.unwrap(), "kpi");
+        let label = widget.child.unwrap();
+        assert_eq!(label.combinator, Combinator::Child);
+        assert_eq!(label.identifier.unwrap(), "label");
+        assert!(label.child.is_none());
+    }
+
+    #[test]
+    fn parse_parent_and_sibling_combinators() {
+        let tokens = lex_selector("column < widget ~ widget + tile:has(< page)");
+        let sel = SelectorParser::new(tokens).parse().unwrap();
+        let parent = sel.child.unwrap();
+        assert_eq!(parent.combinator, Combinator::Parent);
+        let sibling = parent.child.unwrap();
+        assert_eq!(sibling.combinator, Combinator::Sibling);
+        let tile = sibling.child.unwrap();
+        assert_eq!(tile.combinator, Combinator::NextSibling);
+        match tile.pseudo_classes[0] {
+            PseudoClass::Has(ref selectors) => assert_eq!(selectors[0].combinator, Combinator::Parent),
+            ref other => panic!("Expected :has(), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_predicates() {
+        let s = "column.c1{format=formatterLTR}{size}{label^='Acc'}{value~=\"^\\d+$\"}{scale=0.5}";
+        let tokens = lex_selector(s);
+        let parser = SelectorParser::new(tokens);
+        let sel = parser.parse().unwrap();
+        assert_eq!(sel.identifier.unwrap(), "c1");
+        let fields: Vec<&str> = sel.predicates.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(fields, vec!["format", "size", "label", "value", "scale"]);
+        match sel.predicates[0].test {
+            Some(ValueTest::Equals(ref v)) => assert_eq!(v, "formatterLTR"),
+            ref test => panic!("unexpected {:?}", test),
+        }
+        assert!(sel.predicates[1].test.is_none());
+        match sel.predicates[2].test {
+            Some(ValueTest::StartsWith(ref v)) => assert_eq!(v, "Acc"),
+            ref test => panic!("unexpected {:?}", test),
+        }
+        match sel.predicates[3].test {
+            Some(ValueTest::Matches(ref r)) => assert_eq!(r.as_str(), "^\\d+$"),
+            ref test => panic!("unexpected {:?}", test),
+        }
+        match sel.predicates[4].test {
+            Some(ValueTest::Equals(ref v)) => assert_eq!(v, "0.5"),
+            ref test => panic!("unexpected {:?}", test),
+        }
+
+        let parser = SelectorParser::new(lex_selector("{label~='('}"));
+        assert!(parser.parse().is_err());
+
+        let value = |selector: &str| match SelectorParser::new(lex_selector(selector)).parse() {
+            Ok(sel) => match sel.predicates[0].test {
+                Some(ValueTest::Equals(ref v)) => Ok(v.clone()),
+                ref test => panic!("unexpected {:?}", test),
+            },
+            Err(e) => Err(e.message),
+        };
+        assert_eq!(value("{size=-1}"), Ok("-1".to_string()));
+        assert_eq!(value("{size = +1.5}"), Ok("+1.5".to_string()));
+        assert_eq!(value("{label='Acc Health'}"), Ok("Acc Health".to_string()));
+        assert_eq!(value("{label=''}"), Ok("".to_string()));
+        assert_eq!(value("{x=}"), Err("Expected a value, got '}'".to_string()));
+        assert_eq!(value("{label=Acc Health}"), Err("Expected '}' after Acc, quote values with spaces".to_string()));
+    }
+
+    #[test]
+    fn parse_lists_and_negation() {
+        let s = "tile, * > column:not([metric], .c1{hidden}), .label";
+        let parser = SelectorParser::new(lex_selector(s));
+        let selectors = parser.parse_list().unwrap();
+        assert_eq!(selectors.len(), 3);
+        assert_eq!(selectors[0].main_type.as_ref().unwrap(), "tile");
+        assert!(selectors[1].main_type.is_none());
+        let column = selectors[1].child.as_ref().unwrap();
+        assert_eq!(column.main_type.as_ref().unwrap(), "column");
+        match column.pseudo_classes[0] {
+            PseudoClass::Not(ref negated) => {
+                assert_eq!(negated.len(), 2);
+                assert_eq!(negated[0].sub_type.as_ref().unwrap(), "metric");
+                assert_eq!(negated[1].identifier.as_ref().unwrap(), "c1");
+                assert_eq!(negated[1].predicates[0].field, "hidden");
+            }
+            ref class => panic!("unexpected {:?}", class),
+        }
+        assert_eq!(selectors[2].identifier.as_ref().unwrap(), "label");
 
+        assert!(SelectorParser::new(lex_selector("column:not(page > tile)")).parse_list().is_err());
+        assert!(SelectorParser::new(lex_selector("column:nope")).parse_list().is_err());
+    }
+
+    #[test]
+    fn parse_structural_pseudo_classes() {
+        let s = "page:root:has(> widget:not({label}), tile column):first-child > column:nth-child(2):last-child:empty";
+        let parser = SelectorParser::new(lex_selector(s));
+        let sel = parser.parse().unwrap();
+        assert_eq!(sel.pseudo_classes.len(), 3);
+        match sel.pseudo_classes[0] {
+            PseudoClass::Root => {}
+            ref class => panic!("unexpected {:?}", class),
+        }
+        match sel.pseudo_classes[1] {
+            PseudoClass::Has(ref selectors) => {
+                assert_eq!(selectors.len(), 2);
+                assert_eq!(selectors[0].combinator, Combinator::Child);
+                assert_eq!(selectors[0].main_type.as_ref().unwrap(), "widget");
+                assert_eq!(selectors[1].combinator, Combinator::Descendant);
+                assert_eq!(selectors[1].child.as_ref().unwrap().main_type.as_ref().unwrap(), "column");
+            }
+            ref class => panic!("unexpected {:?}", class),
+        }
+        match sel.pseudo_classes[2] {
+            PseudoClass::FirstChild => {}
+            ref class => panic!("unexpected {:?}", class),
+        }
+        let column = sel.child.unwrap();
+        assert_eq!(column.pseudo_classes.len(), 3);
+        match column.pseudo_classes[0] {
+            PseudoClass::NthChild(n) => assert_eq!(n, 2),
+            ref class => panic!("unexpected {:?}", class),
+        }
+
+        assert!(SelectorParser::new(lex_selector("column:nth-child(0)")).parse().is_err());
+    }
+
+    #[test]
+    fn parse_ids_and_references() {
+        let parser = SelectorParser::new(lex_selector("widget#kpi1@default[kpi]{color=#82D854}"));
+        let sel = parser.parse().unwrap();
+        assert_eq!(sel.main_type.unwrap(), "widget");
+        assert_eq!(sel.identifier.unwrap(), "kpi1");
+        assert_eq!(sel.reference.unwrap(), "default");
+        assert_eq!(sel.sub_type.unwrap(), "kpi");
+        match sel.predicates[0].test {
+            Some(ValueTest::Equals(ref v)) => assert_eq!(v, "#82D854"),
+            ref test => panic!("unexpected {:?}", test),
+        }
+
+        let parser = SelectorParser::new(lex_selector("[@cr.base] > #overview"));
+        let sel = parser.parse().unwrap();
+        assert!(sel.main_type.is_none());
+        assert_eq!(sel.reference.unwrap(), "cr.base");
+        assert_eq!(sel.child.unwrap().identifier.unwrap(), "overview");
+    }
+}
@@ -0,0 +1,380 @@
+use std::hash::{Hash, Hasher};
+use parse::{AstEntityNode, EntityExprRef, EntityFieldRef, EntityRef, Expr, NodeRef, ParseResult};
+
+// Structural comparison of AST subtrees. Two nodes are equal when they describe the same CDL, no matter
+// where they are stored in the arenas or which document they come from.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct StructuralOptions {
+    /// Ignore the `#id` of entities
+    pub ignore_ids: bool,
+    /// Ignore `label` fields
+    pub ignore_labels: bool,
+}
+
+pub fn nodes_equal(a_pr: &ParseResult, a: NodeRef, b_pr: &ParseResult, b: NodeRef, options: StructuralOptions) -> bool {
+    let cmp = Comparer { a: a_pr, b: b_pr, options };
+    match (a, b) {
+        (NodeRef::Entity(a), NodeRef::Entity(b)) => cmp.entities(a, b),
+        (NodeRef::Field(a), NodeRef::Field(b)) => cmp.fields(a, b),
+        (NodeRef::Expr(a), NodeRef::Expr(b)) => cmp.exprs(a, b),
+        _ => false,
+    }
+}
+
+pub fn entities_equal(a_pr: &ParseResult, a: EntityRef, b_pr: &ParseResult, b: EntityRef, options: StructuralOptions) -> bool {
+    nodes_equal(a_pr, NodeRef::Entity(a), b_pr, NodeRef::Entity(b), options)
+}
+
+pub fn fields_equal(a_pr: &ParseResult, a: EntityFieldRef, b_pr: &ParseResult, b: EntityFieldRef, options: StructuralOptions) -> bool {
+    nodes_equal(a_pr, NodeRef::Field(a), b_pr, NodeRef::Field(b), options)
+}
+
+pub fn exprs_equal(a_pr: &ParseResult, a: EntityExprRef, b_pr: &ParseResult, b: EntityExprRef) -> bool {
+    nodes_equal(a_pr, NodeRef::Expr(a), b_pr, NodeRef::Expr(b), StructuralOptions::default())
+}
+
+/// A hash of the structure of a node, that stays the same between runs and versions of the compiler.
+pub fn structural_hash(pr: &ParseResult, node: NodeRef, options: StructuralOptions) -> u64 {
+    let mut hasher = StableHasher::new();
+    let writer = HashWriter { pr, options };
+    match node {
+        NodeRef::Entity(e) => writer.entity(e, &mut hasher),
+        NodeRef::Field(f) => writer.field(f, &mut hasher),
+        NodeRef::Expr(e) => writer.expr(e, &mut hasher),
+    }
+    hasher.finish()
+}
+
+/// Wraps a node so it can be used as a `HashMap` key, keys are equal when the nodes are structurally equal.
+#[derive(Debug, Clone, Copy)]
+pub struct StructuralKey<'a> {
+    pub pr: &'a ParseResult,
+    pub node: NodeRef,
+    pub options: StructuralOptions,
+    hash: u64,
+}
+
+impl<'a> StructuralKey<'a> {
+    pub fn new(pr: &'a ParseResult, node: NodeRef, options: StructuralOptions) -> StructuralKey<'a> {
+        StructuralKey {
+            pr,
+            node,
+            options,
+            hash: structural_hash(pr, node, options),
+        }
+    }
+
+    pub fn entity(pr: &'a ParseResult, entity: EntityRef, options: StructuralOptions) -> StructuralKey<'a> {
+        StructuralKey::new(pr, NodeRef::Entity(entity), options)
+    }
+
+    pub fn field(pr: &'a ParseResult, field: EntityFieldRef, options: StructuralOptions) -> StructuralKey<'a> {
+        StructuralKey::new(pr, NodeRef::Field(field), options)
+    }
+
+    pub fn expr(pr: &'a ParseResult, expr: EntityExprRef) -> StructuralKey<'a> {
+        StructuralKey::new(pr, NodeRef::Expr(expr), StructuralOptions::default())
+    }
+
+    pub fn structural_hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl<'a> PartialEq for StructuralKey<'a> {
+    fn eq(&self, other: &StructuralKey) -> bool {
+        self.options == other.options
+            && self.hash == other.hash
+            && nodes_equal(self.pr, self.node, other.pr, other.node, self.options)
+    }
+}
+
+impl<'a> Eq for StructuralKey<'a> {}
+
+impl<'a> Hash for StructuralKey<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+fn visible_fields(pr: &ParseResult, entity: &AstEntityNode, options: StructuralOptions) -> Vec<EntityFieldRef> {
+    entity.fields.iter()
+        .cloned()
+        .filter(|f| !(options.ignore_labels && pr.get_field(*f).identifier == "label"))
+        .collect()
+}
+
+// -0 and 0 are the same number, and should hash the same
+fn number_bits(value: f64) -> u64 {
+    if value == 0.0 { 0 } else { value.to_bits() }
+}
+
+struct Comparer<'a> {
+    a: &'a ParseResult,
+    b: &'a ParseResult,
+    options: StructuralOptions,
+}
+
+impl<'a> Comparer<'a> {
+    fn entities(&self, a: EntityRef, b: EntityRef) -> bool {
+        let (ea, eb) = (self.a.get_entity(a), self.b.get_entity(b));
+        if ea.main_type != eb.main_type || ea.sub_type != eb.sub_type || ea.reference != eb.reference {
+            return false;
+        }
+        if !self.options.ignore_ids && ea.identifier != eb.identifier {
+            return false;
+        }
+        let (fa, fb) = (visible_fields(self.a, ea, self.options), visible_fields(self.b, eb, self.options));
+        fa.len() == fb.len()
+            && ea.children.len() == eb.children.len()
+            && fa.iter().zip(fb.iter()).all(|(x, y)| self.fields(*x, *y))
+            && ea.children.iter().zip(eb.children.iter()).all(|(x, y)| self.entities(*x, *y))
+    }
+
+    fn fields(&self, a: EntityFieldRef, b: EntityFieldRef) -> bool {
+        let (fa, fb) = (self.a.get_field(a), self.b.get_field(b));
+        fa.identifier == fb.identifier && self.exprs(fa.value, fb.value)
+    }
+
+    fn exprs(&self, a: EntityExprRef, b: EntityExprRef) -> bool {
+        match (self.a.get_expr(a), self.b.get_expr(b)) {
+            (Expr::String(x), Expr::String(y)) => x.value == y.value,
+            (Expr::Identifier(x), Expr::Identifier(y)) => x.value == y.value,
+            (Expr::Reference(x), Expr::Reference(y)) => x.value == y.value,
+            (Expr::Number(x), Expr::Number(y)) => number_bits(x.value) == number_bits(y.value),
+            (Expr::VPath(x), Expr::VPath(y)) => {
+                x.table == y.table && x.sub_table == y.sub_table && x.field == y.field && x.sub_field == y.sub_field
+            }
+            (Expr::Function(x), Expr::Function(y)) => {
+                x.identifier == y.identifier
+                    && x.argument_list.len() == y.argument_list.len()
+                    && x.argument_list.iter().zip(y.argument_list.iter()).all(|(p, q)| self.exprs(*p, *q))
+            }
+            (Expr::Operator(x), Expr::Operator(y)) => {
+                x.operator == y.operator && self.exprs(x.left_side, y.left_side) && self.exprs(x.right_side, y.right_side)
+            }
+            (Expr::UnaryOperator(x), Expr::UnaryOperator(y)) => x.operator == y.operator && self.exprs(x.expr, y.expr),
+            _ => false,
+        }
+    }
+}
+
+struct HashWriter<'a> {
+    pr: &'a ParseResult,
+    options: StructuralOptions,
+}
+
+impl<'a> HashWriter<'a> {
+    fn entity(&self, entity: EntityRef, h: &mut StableHasher) {
+        let node = self.pr.get_entity(entity);
+        h.write_tag(b'E');
+        h.write_str(&node.main_type);
+        h.write_opt(&node.sub_type);
+        h.write_opt(&node.reference);
+        if !self.options.ignore_ids {
+            h.write_opt(&node.identifier);
+        }
+        let fields = visible_fields(self.pr, node, self.options);
+        h.write_u64(fields.len() as u64);
+        for field in fields {
+            self.field(field, h);
+        }
+        h.write_u64(node.children.len() as u64);
+        for child in &node.children {
+            self.entity(*child, h);
+        }
+    }
+
+    fn field(&self, field: EntityFieldRef, h: &mut StableHasher) {
+        let node = self.pr.get_field(field);
+        h.write_tag(b'F');
+        h.write_str(&node.identifier);
+        self.expr(node.value, h);
+    }
+
+    fn expr(&self, expr: EntityExprRef, h: &mut StableHasher) {
+        match self.pr.get_expr(expr) {
+            Expr::String(node) => {
+                h.write_tag(b's');
+                h.write_str(&node.value);
+            }
+            Expr::Identifier(node) => {
+                h.write_tag(b'i');
+                h.write_str(&node.value);
+            }
+            Expr::Reference(node) => {
+                h.write_tag(b'r');
+                h.write_str(&node.value);
+            }
+            Expr::Number(node) => {
+                h.write_tag(b'n');
+                h.write_u64(number_bits(node.value));
+            }
+            Expr::VPath(node) => {
+                h.write_tag(b'v');
+                h.write_opt(&node.table);
+                h.write_opt(&node.sub_table);
+                h.write_opt(&node.field);
+                h.write_opt(&node.sub_field);
+            }
+            Expr::Function(node) => {
+                h.write_tag(b'f');
+                h.write_str(&node.identifier);
+                h.write_u64(node.argument_list.len() as u64);
+                for arg in &node.argument_list {
+                    self.expr(*arg, h);
+                }
+            }
+            Expr::Operator(node) => {
+                h.write_tag(b'o');
+                h.write_u64(node.operator as u64);
+                self.expr(node.left_side, h);
+                self.expr(node.right_side, h);
+            }
+            Expr::UnaryOperator(node) => {
+                h.write_tag(b'u');
+                h.write_u64(node.operator as u64);
+                self.expr(node.expr, h);
+            }
+        }
+    }
+}
+
+// 64 bit FNV-1a, std's hashers are allowed to change between releases
+struct StableHasher {
+    state: u64,
+}
+
+impl StableHasher {
+    fn new() -> StableHasher {
+        StableHasher { state: 0xcbf2_9ce4_8422_2325 }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= u64::from(*byte);
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_tag(&mut self, tag: u8) {
+        self.write_bytes(&[tag]);
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write_bytes(value.as_bytes());
+    }
+
+    fn write_opt(&mut self, value: &Option<String>) {
+        match value {
+            Some(s) => {
+                self.write_tag(1);
+                self.write_str(s);
+            }
+            None => self.write_tag(0),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use lex::Lexer;
+    use parse::{NodeRef, ParseResult, Parser};
+    use structural::{entities_equal, exprs_equal, structural_hash, StructuralKey, StructuralOptions};
+
+    fn parse(cdl: &str) -> ParseResult {
+        let lexer = Lexer::new(cdl.to_string());
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        parser.parse().unwrap()
+    }
+
+    const SCRIPT_A: &str = "config report cr {
+    formatter number formatterLTR {
+        numberDecimals: 1
+        decimalSeparator: \".\"
+    }
+    formatter number floatNumber {
+        numberDecimals: 3
+    }
+}
+page #overview {
+    widget kpi #kpi1 {
+        label: \"KPI\"
+        value: average(score(survey:Q7), @cr.currentPeriodB2b)
+    }
+}
+";
+
+    const SCRIPT_B: &str = "page #details {
+    widget kpi #kpi2 {
+        label: \"Other KPI\"
+        value: average(score(survey:Q7), @cr.currentPeriodB2b)
+    }
+    widget kpi #kpi3 {
+        value: average(score(survey:Q1), @cr.currentPeriodB2b)
+    }
+}
+config report cr {
+    formatter number formatterLTR {
+        numberDecimals: 1.0
+        decimalSeparator: \".\"
+    }
+}
+";
+
+    #[test]
+    fn equal_across_documents() {
+        let (a, b) = (parse(SCRIPT_A), parse(SCRIPT_B));
+        let strict = StructuralOptions::default();
+        let loose = StructuralOptions { ignore_ids: true, ignore_labels: true };
+        let ltr_a = a.get_entity(a.root.children[0]).children[0];
+        let ltr_b = b.get_entity(b.root.children[1]).children[0];
+        assert!(entities_equal(&a, ltr_a, &b, ltr_b, strict));
+        assert_eq!(structural_hash(&a, NodeRef::Entity(ltr_a), strict), structural_hash(&b, NodeRef::Entity(ltr_b), strict));
+
+        let kpi1 = a.get_entity(a.root.children[1]).children[0];
+        let kpi2 = b.get_entity(b.root.children[0]).children[0];
+        let kpi3 = b.get_entity(b.root.children[0]).children[1];
+        assert!(!entities_equal(&a, kpi1, &b, kpi2, strict));
+        assert!(entities_equal(&a, kpi1, &b, kpi2, loose));
+        assert!(!entities_equal(&a, kpi1, &b, kpi3, loose));
+
+        let value_a = a.get_field(a.get_entity(kpi1).fields[1]).value;
+        let value_b = b.get_field(b.get_entity(kpi2).fields[1]).value;
+        assert!(exprs_equal(&a, value_a, &b, value_b));
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        let a = parse("widget kpi {\n    value: 1 + x\n}\n");
+        assert_eq!(structural_hash(&a, NodeRef::Entity(0), StructuralOptions::default()), 0x8f42_bbae_9463_508e);
+    }
+
+    #[test]
+    fn dedup_with_hash_map() {
+        let (a, b) = (parse(SCRIPT_A), parse(SCRIPT_B));
+        let mut formatters: HashMap<StructuralKey, Vec<String>> = HashMap::new();
+        for pr in &[&a, &b] {
+            for (index, entity) in pr.entities.iter().enumerate() {
+                if entity.main_type == "formatter" {
+                    let key = StructuralKey::entity(pr, index, StructuralOptions::default());
+                    formatters.entry(key).or_default().push(entity.identifier.clone().unwrap_or_default());
+                }
+            }
+        }
+        assert_eq!(formatters.len(), 2);
+        assert!(formatters.values().any(|names| names.len() == 2));
+    }
+}
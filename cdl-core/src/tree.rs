@@ -0,0 +1,250 @@
+use build::{self, EntityBuilder, ExprBuilder};
+use parse::{AstVPathNode, EntityExprRef, EntityRef, Expr, ParseResult};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Nested form of a ParseResult, where entities own their fields and children and expressions own their
+// operands. Easier to read than the arena form, and with the `serde` feature it is the JSON shape
+// handed to other tools.
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct TreeEntity {
+    pub main_type: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub sub_type: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub identifier: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub reference: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub fields: Vec<TreeField>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub children: Vec<TreeEntity>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TreeField {
+    pub identifier: String,
+    pub value: TreeExpr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "camelCase"))]
+pub enum TreeExpr {
+    String { value: String },
+    Identifier { value: String },
+    Number { value: f64, text: String },
+    Reference { value: String },
+    #[cfg_attr(feature = "serde", serde(rename = "vpath", rename_all = "camelCase"))]
+    VPath {
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        table: Option<String>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        sub_table: Option<String>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        field: Option<String>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        sub_field: Option<String>,
+    },
+    Function { identifier: String, arguments: Vec<TreeExpr> },
+    Operator { operator: char, left: Box<TreeExpr>, right: Box<TreeExpr> },
+    UnaryOperator { operator: char, expr: Box<TreeExpr> },
+}
+
+/// Converts the root entities of a document, and everything below them, to the nested form.
+pub fn to_tree(pr: &ParseResult) -> Vec<TreeEntity> {
+    pr.root.children.iter().map(|e| entity_to_tree(pr, *e)).collect()
+}
+
+/// Builds a document from the nested form, with the arena laid out the way the parser does it.
+pub fn from_tree(entities: &[TreeEntity]) -> ParseResult {
+    let mut pr = ParseResult::new();
+    for entity in entities {
+        let index = add_entity(&mut pr, entity);
+        pr.root.children.push(index);
+    }
+    pr
+}
+
+pub fn entity_to_tree(pr: &ParseResult, entity: EntityRef) -> TreeEntity {
+    let node = pr.get_entity(entity);
+    TreeEntity {
+        main_type: node.main_type.clone(),
+        sub_type: node.sub_type.clone(),
+        identifier: node.identifier.clone(),
+        reference: node.reference.clone(),
+        fields: node.fields.iter().map(|f| {
+            let field = pr.get_field(*f);
+            TreeField {
+                identifier: field.identifier.clone(),
+                value: expr_to_tree(pr, field.value),
+            }
+        }).collect(),
+        children: node.children.iter().map(|c| entity_to_tree(pr, *c)).collect(),
+    }
+}
+
+pub fn expr_to_tree(pr: &ParseResult, expr: EntityExprRef) -> TreeExpr {
+    match pr.get_expr(expr) {
+        Expr::String(node) => TreeExpr::String { value: node.value.clone() },
+        Expr::Identifier(node) => TreeExpr::Identifier { value: node.value.clone() },
+        Expr::Number(node) => TreeExpr::Number { value: node.value, text: node.text_rep.clone() },
+        Expr::Reference(node) => TreeExpr::Reference { value: node.value.clone() },
+        Expr::VPath(node) => TreeExpr::VPath {
+            table: node.table.clone(),
+            sub_table: node.sub_table.clone(),
+            field: node.field.clone(),
+            sub_field: node.sub_field.clone(),
+        },
+        Expr::Function(node) => TreeExpr::Function {
+            identifier: node.identifier.clone(),
+            arguments: node.argument_list.iter().map(|a| expr_to_tree(pr, *a)).collect(),
+        },
+        Expr::Operator(node) => TreeExpr::Operator {
+            operator: node.operator,
+            left: Box::new(expr_to_tree(pr, node.left_side)),
+            right: Box::new(expr_to_tree(pr, node.right_side)),
+        },
+        Expr::UnaryOperator(node) => TreeExpr::UnaryOperator {
+            operator: node.operator,
+            expr: Box::new(expr_to_tree(pr, node.expr)),
+        },
+    }
+}
+
+/// Adds an entity and everything below it to a document without attaching it to the root.
+pub fn add_entity(pr: &mut ParseResult, entity: &TreeEntity) -> EntityRef {
+    entity_builder(entity).add_to(pr)
+}
+
+pub fn add_expr(pr: &mut ParseResult, expr: &TreeExpr) -> EntityExprRef {
+    expr_builder(expr).add_to(pr)
+}
+
+fn entity_builder(entity: &TreeEntity) -> EntityBuilder {
+    let mut builder = build::entity(&entity.main_type);
+    if let Some(ref sub_type) = entity.sub_type {
+        builder = builder.sub_type(sub_type);
+    }
+    if let Some(ref identifier) = entity.identifier {
+        builder = builder.id(identifier);
+    }
+    if let Some(ref reference) = entity.reference {
+        builder = builder.reference(reference);
+    }
+    for field in &entity.fields {
+        builder = builder.field(&field.identifier, expr_builder(&field.value));
+    }
+    for child in &entity.children {
+        builder = builder.child(entity_builder(child));
+    }
+    builder
+}
+
+fn expr_builder(expr: &TreeExpr) -> ExprBuilder {
+    match expr {
+        TreeExpr::String { value } => ExprBuilder::String(value.clone()),
+        TreeExpr::Identifier { value } => ExprBuilder::Identifier(value.clone()),
+        TreeExpr::Number { value, text } => ExprBuilder::Number(*value, text.clone()),
+        TreeExpr::Reference { value } => ExprBuilder::Reference(value.clone()),
+        TreeExpr::VPath { table, sub_table, field, sub_field } => ExprBuilder::VPath(AstVPathNode {
+            table: table.clone(),
+            sub_table: sub_table.clone(),
+            field: field.clone(),
+            sub_field: sub_field.clone(),
+        }),
+        TreeExpr::Function { identifier, arguments } => {
+            ExprBuilder::Function(identifier.clone(), arguments.iter().map(expr_builder).collect())
+        }
+        TreeExpr::Operator { operator, left, right } => {
+            ExprBuilder::Operator(*operator, Box::new(expr_builder(left)), Box::new(expr_builder(right)))
+        }
+        TreeExpr::UnaryOperator { operator, expr } => {
+            ExprBuilder::UnaryOperator(*operator, Box::new(expr_builder(expr)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lex::Lexer;
+    use parse::{ParseResult, Parser};
+    use tree::{from_tree, to_tree, TreeExpr};
+
+    const CDL: &str = "datatable kpi #data1 {
+    type: nps
+    vpath: t1:q1
+}
+page #overview {
+    widget kpi #kpi1 @default {
+        label: \"KPI\"
+        value: average(score(survey:Q7), @cr.currentPeriodB2b) / -(1 - x)
+        target: 9.50
+    }
+}
+";
+
+    fn parse(cdl: &str) -> ParseResult {
+        let lexer = Lexer::new(cdl.to_string());
+        let lex_items = lexer.lex().unwrap();
+        let parser = Parser::new(lex_items);
+        parser.parse().unwrap()
+    }
+
+    #[test]
+    fn tree_round_trip() {
+        let pr = parse(CDL);
+        let tree = to_tree(&pr);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree[1].children[0].fields[2].value, TreeExpr::Number { value: 9.5, text: "9.50".to_string() });
+        let back = from_tree(&tree);
+        assert_eq!(format!("{:?}", back), format!("{:?}", pr));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn flat_json_round_trip() {
+        use serde_json;
+
+        let pr = parse(CDL);
+        let json = serde_json::to_string(&pr).unwrap();
+        let back: ParseResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", back), format!("{:?}", pr));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn nested_json_round_trip() {
+        use serde_json;
+        use tree::TreeEntity;
+
+        let pr = parse(CDL);
+        let json = serde_json::to_value(to_tree(&pr)).unwrap();
+        assert_eq!(json[0], json!({
+            "mainType": "datatable",
+            "subType": "kpi",
+            "identifier": "data1",
+            "fields": [
+                { "identifier": "type", "value": { "type": "identifier", "value": "nps" } },
+                { "identifier": "vpath", "value": { "type": "vpath", "table": "t1", "field": "q1" } }
+            ]
+        }));
+        assert_eq!(json[1]["children"][0]["fields"][1]["value"]["right"], json!({
+            "type": "unaryOperator",
+            "operator": "-",
+            "expr": {
+                "type": "operator",
+                "operator": "-",
+                "left": { "type": "number", "value": 1.0, "text": "1" },
+                "right": { "type": "identifier", "value": "x" }
+            }
+        }));
+
+        let tree: Vec<TreeEntity> = serde_json::from_value(json).unwrap();
+        assert_eq!(format!("{:?}", from_tree(&tree)), format!("{:?}", pr));
+    }
+}